@@ -0,0 +1,364 @@
+mod noise;
+mod square;
+mod wave;
+
+use crate::cpu::Mem;
+
+use self::noise::NoiseChannel;
+use self::square::SquareChannel;
+use self::wave::WaveChannel;
+
+/// T-cycles per second, at normal speed
+pub const CPU_FREQUENCY: u32 = 4_194_304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+/// The frame sequencer runs at 512 Hz
+const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+
+/// Read-back OR masks of 0xFF10..=0xFF2F: unused and write-only bits read as 1
+/// https://gbdev.io/pandocs/Audio_Registers.html
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+/// Length timer shared by every channel, disables it when it expires
+/// https://gbdev.io/pandocs/Audio.html#length-timer
+struct LengthCounter {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    fn new(max: u16) -> Self {
+        LengthCounter {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    fn load(&mut self, length: u8) {
+        self.counter = self.max - length as u16;
+    }
+
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    /// Return true when the channel must be disabled
+    fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+
+        self.counter -= 1;
+        self.counter == 0
+    }
+}
+
+/// Volume envelope of the square and noise channels
+/// https://gbdev.io/pandocs/Audio_Registers.html#ff12--nr12-channel-1-volume--envelope
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+    volume: u8,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            timer: 0,
+            volume: 0,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        self.initial_volume = data >> 4;
+        self.increase = data & 0b1000 != 0;
+        self.period = data & 0b111;
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+/// Audio Processing Unit
+/// https://gbdev.io/pandocs/Audio.html
+///
+/// Produces interleaved stereo `f32` samples at the host sample rate,
+/// to be drained by the frontend with `take_samples`.
+pub struct APU {
+    square1: SquareChannel,
+    square2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    /// raw values of 0xFF10..=0xFF2F, for read-back
+    registers: [u8; 0x20],
+    enabled: bool,
+    frame_sequencer_cycles: u32,
+    frame_sequencer_step: u8,
+    sample_rate: u32,
+    /// T-cycles * sample_rate, a sample is due every CPU_FREQUENCY
+    sample_timer: u64,
+    samples: Vec<f32>,
+}
+
+impl APU {
+    pub fn new(sample_rate: u32) -> Self {
+        APU {
+            square1: SquareChannel::new(true),
+            square2: SquareChannel::new(false),
+            wave: WaveChannel::new(),
+            noise: NoiseChannel::new(),
+            registers: [0; 0x20],
+            enabled: false,
+            frame_sequencer_cycles: 0,
+            frame_sequencer_step: 0,
+            sample_rate,
+            sample_timer: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_timer = 0;
+    }
+
+    /// Drain the interleaved (left, right) samples produced so far
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// `time` is in T-cycles at normal speed
+    pub fn execute_cycle(&mut self, time: u32) {
+        if self.enabled {
+            self.square1.execute_cycle(time);
+            self.square2.execute_cycle(time);
+            self.wave.execute_cycle(time);
+            self.noise.execute_cycle(time);
+
+            self.frame_sequencer_cycles += time;
+            while self.frame_sequencer_cycles >= FRAME_SEQUENCER_PERIOD {
+                self.frame_sequencer_cycles -= FRAME_SEQUENCER_PERIOD;
+                self.clock_frame_sequencer();
+            }
+        }
+
+        self.sample_timer += time as u64 * self.sample_rate as u64;
+        while self.sample_timer >= CPU_FREQUENCY as u64 {
+            self.sample_timer -= CPU_FREQUENCY as u64;
+            self.push_sample();
+        }
+    }
+
+    /// https://gbdev.io/pandocs/Audio_details.html#div-apu
+    ///
+    /// Step   Length Ctr  Vol Env     Sweep
+    /// ---------------------------------------
+    /// 0      Clock       -           -
+    /// 1      -           -           -
+    /// 2      Clock       -           Clock
+    /// 3      -           -           -
+    /// 4      Clock       -           -
+    /// 5      -           -           -
+    /// 6      Clock       -           Clock
+    /// 7      -           Clock       -
+    fn clock_frame_sequencer(&mut self) {
+        match self.frame_sequencer_step {
+            0 | 4 => self.clock_length(),
+            2 | 6 => {
+                self.clock_length();
+                self.square1.clock_sweep();
+            }
+            7 => {
+                self.square1.clock_envelope();
+                self.square2.clock_envelope();
+                self.noise.clock_envelope();
+            }
+            _ => (),
+        }
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    fn clock_length(&mut self) {
+        self.square1.clock_length();
+        self.square2.clock_length();
+        self.wave.clock_length();
+        self.noise.clock_length();
+    }
+
+    /// Mix the channels as selected by NR51 and scale by NR50
+    fn push_sample(&mut self) {
+        // don't grow forever when nobody drains the samples
+        if self.samples.len() >= self.sample_rate as usize * 2 {
+            return;
+        }
+
+        let outputs = [
+            dac(self.square1.dac_enabled(), self.square1.output()),
+            dac(self.square2.dac_enabled(), self.square2.output()),
+            dac(self.wave.dac_enabled(), self.wave.output()),
+            dac(self.noise.dac_enabled(), self.noise.output()),
+        ];
+
+        let nr50 = self.registers[0x14];
+        let nr51 = self.registers[0x15];
+        let (mut left, mut right) = (0.0, 0.0);
+
+        if self.enabled {
+            for (i, output) in outputs.iter().enumerate() {
+                if nr51 & (0b0001_0000 << i) != 0 {
+                    left += output;
+                }
+                if nr51 & (0b0000_0001 << i) != 0 {
+                    right += output;
+                }
+            }
+        }
+
+        let left_volume = (((nr50 >> 4) & 0b111) + 1) as f32 / 8.0;
+        let right_volume = ((nr50 & 0b111) + 1) as f32 / 8.0;
+        self.samples.push(left / 4.0 * left_volume);
+        self.samples.push(right / 4.0 * right_volume);
+    }
+
+    fn power_off(&mut self) {
+        for addr in 0xFF10..=0xFF25 {
+            self.write_register(addr, 0);
+        }
+        self.square1.enabled = false;
+        self.square2.enabled = false;
+        self.wave.enabled = false;
+        self.noise.enabled = false;
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        self.registers[addr as usize - 0xFF10] = data;
+
+        match addr {
+            0xFF10 => self.square1.write_sweep(data),
+            0xFF11 => self.square1.write_length_duty(data),
+            0xFF12 => self.square1.write_envelope(data),
+            0xFF13 => self.square1.write_frequency_lo(data),
+            0xFF14 => self.square1.write_frequency_hi(data),
+            0xFF16 => self.square2.write_length_duty(data),
+            0xFF17 => self.square2.write_envelope(data),
+            0xFF18 => self.square2.write_frequency_lo(data),
+            0xFF19 => self.square2.write_frequency_hi(data),
+            0xFF1A => self.wave.write_dac(data),
+            0xFF1B => self.wave.write_length(data),
+            0xFF1C => self.wave.write_volume(data),
+            0xFF1D => self.wave.write_frequency_lo(data),
+            0xFF1E => self.wave.write_frequency_hi(data),
+            0xFF20 => self.noise.write_length(data),
+            0xFF21 => self.noise.write_envelope(data),
+            0xFF22 => self.noise.write_polynomial(data),
+            0xFF23 => self.noise.write_control(data),
+            _ => (),
+        }
+    }
+}
+
+/// Convert a digital channel output (0..=15) to an analog value (-1.0..=1.0)
+fn dac(enabled: bool, output: u8) -> f32 {
+    if !enabled {
+        return 0.0;
+    }
+    output as f32 / 7.5 - 1.0
+}
+
+impl Mem for APU {
+    fn mem_read_u8(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF26 => {
+                let status = (self.enabled as u8) << 7
+                    | (self.noise.enabled as u8) << 3
+                    | (self.wave.enabled as u8) << 2
+                    | (self.square2.enabled as u8) << 1
+                    | self.square1.enabled as u8;
+                status | READ_MASKS[0x16]
+            }
+            0xFF10..=0xFF2F => {
+                let idx = addr as usize - 0xFF10;
+                self.registers[idx] | READ_MASKS[idx]
+            }
+            0xFF30..=0xFF3F => self.wave.wave_ram[addr as usize - 0xFF30],
+            _ => panic!("APU can't read {addr:4x}"),
+        }
+    }
+
+    fn mem_write_u8(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xFF26 => {
+                let enabled = data & 0b1000_0000 != 0;
+                if self.enabled && !enabled {
+                    self.power_off();
+                } else if !self.enabled && enabled {
+                    self.frame_sequencer_step = 0;
+                    self.frame_sequencer_cycles = 0;
+                }
+                self.enabled = enabled;
+            }
+            // registers are read-only while the APU is off
+            0xFF10..=0xFF25 if !self.enabled => (),
+            0xFF10..=0xFF2F => self.write_register(addr, data),
+            0xFF30..=0xFF3F => self.wave.wave_ram[addr as usize - 0xFF30] = data,
+            _ => panic!("APU can't write {addr:4x}"),
+        }
+    }
+}
+
+#[test]
+fn test_sample_rate() {
+    let mut apu = APU::new(44100);
+    apu.execute_cycle(CPU_FREQUENCY / 2);
+    assert_eq!(apu.take_samples().len(), 22050 * 2);
+    assert!(apu.take_samples().is_empty());
+}
+
+#[test]
+fn test_length_counter_disables_channel() {
+    let mut apu = APU::new(44100);
+    apu.mem_write_u8(0xFF26, 0x80);
+    apu.mem_write_u8(0xFF12, 0xF0);
+    apu.mem_write_u8(0xFF11, 62); // 2 length clocks left
+    apu.mem_write_u8(0xFF14, 0xC0);
+    assert_eq!(apu.mem_read_u8(0xFF26) & 0b1, 1);
+
+    // length is clocked at 256 Hz
+    apu.execute_cycle(FRAME_SEQUENCER_PERIOD * 3);
+    assert_eq!(apu.mem_read_u8(0xFF26) & 0b1, 0);
+}
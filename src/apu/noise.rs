@@ -0,0 +1,112 @@
+use super::{Envelope, LengthCounter};
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Channel 4, pseudo-random output of a linear feedback shift register
+/// https://gbdev.io/pandocs/Audio_details.html#noise-channel-ch4
+pub struct NoiseChannel {
+    pub enabled: bool,
+    dac_enabled: bool,
+    /// NR43
+    polynomial: u8,
+    lfsr: u16,
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    pub fn new() -> Self {
+        NoiseChannel {
+            enabled: false,
+            dac_enabled: false,
+            polynomial: 0,
+            lfsr: 0x7FFF,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    pub fn execute_cycle(&mut self, time: u32) {
+        let mut time = time;
+        while time > 0 {
+            if self.timer > time {
+                self.timer -= time;
+                return;
+            }
+
+            time -= self.timer;
+            self.timer = self.period();
+            self.clock_lfsr();
+        }
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[(self.polynomial & 0b111) as usize] << (self.polynomial >> 4)
+    }
+
+    fn clock_lfsr(&mut self) {
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+
+        // 7-bit mode
+        if self.polynomial & 0b1000 != 0 {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (bit << 6);
+        }
+    }
+
+    /// Digital output, 0..=15
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 == 1 {
+            return 0;
+        }
+        self.envelope.volume
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// NR41
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load(data & 0b0011_1111);
+    }
+
+    /// NR42
+    pub fn write_envelope(&mut self, data: u8) {
+        self.envelope.write(data);
+        self.dac_enabled = data & 0xF8 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    /// NR43
+    pub fn write_polynomial(&mut self, data: u8) {
+        self.polynomial = data;
+    }
+
+    /// NR44
+    pub fn write_control(&mut self, data: u8) {
+        self.length.enabled = data & 0b0100_0000 != 0;
+
+        if data & 0b1000_0000 != 0 {
+            self.enabled = self.dac_enabled;
+            self.length.trigger();
+            self.timer = self.period();
+            self.envelope.trigger();
+            self.lfsr = 0x7FFF;
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use super::{Envelope, LengthCounter};
+
+/// https://gbdev.io/pandocs/Audio_details.html#square-wave
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+/// Frequency sweep of channel 1 (NR10)
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow: u16,
+    enabled: bool,
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            shadow: 0,
+            enabled: false,
+        }
+    }
+
+    fn calc_frequency(&self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.shadow.wrapping_sub(delta)
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+}
+
+/// Channels 1 and 2, only channel 1 has a sweep unit
+pub struct SquareChannel {
+    pub enabled: bool,
+    dac_enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl SquareChannel {
+    pub fn new(with_sweep: bool) -> Self {
+        SquareChannel {
+            enabled: false,
+            dac_enabled: false,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: with_sweep.then(Sweep::new),
+        }
+    }
+
+    pub fn execute_cycle(&mut self, time: u32) {
+        let mut time = time;
+        while time > 0 {
+            if self.timer > time {
+                self.timer -= time;
+                return;
+            }
+
+            time -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    /// Digital output, 0..=15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        DUTY_TABLE[self.duty as usize][self.duty_step as usize] * self.envelope.volume
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let sweep = match self.sweep.as_mut() {
+            Some(sweep) => sweep,
+            None => return,
+        };
+
+        if sweep.timer > 0 {
+            sweep.timer -= 1;
+        }
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.reload_timer();
+
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+
+        let frequency = sweep.calc_frequency();
+        if frequency > 2047 {
+            self.enabled = false;
+        } else if sweep.shift != 0 {
+            sweep.shadow = frequency;
+            self.frequency = frequency;
+            // overflow check again with the new frequency
+            if sweep.calc_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// NRx0, channel 1 only
+    pub fn write_sweep(&mut self, data: u8) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.period = (data >> 4) & 0b111;
+            sweep.negate = data & 0b1000 != 0;
+            sweep.shift = data & 0b111;
+        }
+    }
+
+    /// NRx1
+    pub fn write_length_duty(&mut self, data: u8) {
+        self.duty = data >> 6;
+        self.length.load(data & 0b0011_1111);
+    }
+
+    /// NRx2
+    pub fn write_envelope(&mut self, data: u8) {
+        self.envelope.write(data);
+        self.dac_enabled = data & 0xF8 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    /// NRx3
+    pub fn write_frequency_lo(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x0700) | data as u16;
+    }
+
+    /// NRx4
+    pub fn write_frequency_hi(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x00FF) | ((data as u16 & 0b111) << 8);
+        self.length.enabled = data & 0b0100_0000 != 0;
+
+        if data & 0b1000_0000 != 0 {
+            self.trigger();
+        }
+    }
+
+    /// https://gbdev.io/pandocs/Audio_Registers.html#ff14--nr14-channel-1-period-high--control
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow = self.frequency;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            if sweep.shift != 0 && sweep.calc_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use super::LengthCounter;
+
+/// Channel 3, plays the 32 4-bit samples of wave RAM
+pub struct WaveChannel {
+    pub enabled: bool,
+    dac_enabled: bool,
+    /// NR32 output level, as a right shift of the sample
+    volume_shift: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    length: LengthCounter,
+    pub wave_ram: [u8; 0x10],
+}
+
+impl WaveChannel {
+    pub fn new() -> Self {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            volume_shift: 4,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            length: LengthCounter::new(256),
+            wave_ram: [0; 0x10],
+        }
+    }
+
+    pub fn execute_cycle(&mut self, time: u32) {
+        let mut time = time;
+        while time > 0 {
+            if self.timer > time {
+                self.timer -= time;
+                return;
+            }
+
+            time -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    /// Digital output, 0..=15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let byte = self.wave_ram[self.position as usize / 2];
+        let sample = if self.position & 1 == 0 {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        };
+        sample >> self.volume_shift
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// NR30
+    pub fn write_dac(&mut self, data: u8) {
+        self.dac_enabled = data & 0b1000_0000 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    /// NR31
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load(data);
+    }
+
+    /// NR32
+    pub fn write_volume(&mut self, data: u8) {
+        self.volume_shift = match (data >> 5) & 0b11 {
+            0b00 => 4,
+            0b01 => 0,
+            0b10 => 1,
+            _ => 2,
+        };
+    }
+
+    /// NR33
+    pub fn write_frequency_lo(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x0700) | data as u16;
+    }
+
+    /// NR34
+    pub fn write_frequency_hi(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x00FF) | ((data as u16 & 0b111) << 8);
+        self.length.enabled = data & 0b0100_0000 != 0;
+
+        if data & 0b1000_0000 != 0 {
+            self.enabled = self.dac_enabled;
+            self.length.trigger();
+            self.timer = self.period();
+            self.position = 0;
+        }
+    }
+}
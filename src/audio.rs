@@ -0,0 +1,94 @@
+use std::{
+    collections::VecDeque,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use cpal::{StreamData, UnknownTypeOutputBuffer};
+
+/// Keep at most ~100 ms of stereo samples queued
+const MAX_QUEUED_SAMPLES: usize = 48_000 / 10 * 2;
+
+/// Host audio output through cpal, fed with interleaved stereo samples
+pub struct AudioOutput {
+    pub sample_rate: u32,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl AudioOutput {
+    /// Start playback on the default output device, or return None if there is none
+    pub fn start() -> Option<Self> {
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let (tx, rx) = mpsc::channel();
+
+        let stream_queue = Arc::clone(&queue);
+        thread::spawn(move || {
+            let device = match cpal::default_output_device() {
+                Some(d) => d,
+                None => return,
+            };
+            let format = match device.default_output_format() {
+                Ok(f) => f,
+                Err(_) => return,
+            };
+            let event_loop = cpal::EventLoop::new();
+            let stream_id = match event_loop.build_output_stream(&device, &format) {
+                Ok(id) => id,
+                Err(_) => return,
+            };
+            event_loop.play_stream(stream_id);
+
+            let channels = format.channels as usize;
+            if tx.send(format.sample_rate.0).is_err() {
+                return;
+            }
+
+            event_loop.run(move |_, data| {
+                let mut queue = stream_queue.lock().unwrap();
+                let mut next = || queue.pop_front().unwrap_or(0.0);
+
+                match data {
+                    StreamData::Output {
+                        buffer: UnknownTypeOutputBuffer::F32(mut buffer),
+                    } => fill(&mut buffer, channels, &mut next, |v| v),
+                    StreamData::Output {
+                        buffer: UnknownTypeOutputBuffer::I16(mut buffer),
+                    } => fill(&mut buffer, channels, &mut next, |v| {
+                        (v * i16::MAX as f32) as i16
+                    }),
+                    StreamData::Output {
+                        buffer: UnknownTypeOutputBuffer::U16(mut buffer),
+                    } => fill(&mut buffer, channels, &mut next, |v| {
+                        ((v * 0.5 + 0.5) * u16::MAX as f32) as u16
+                    }),
+                    _ => (),
+                }
+            });
+        });
+
+        let sample_rate = rx.recv().ok()?;
+        Some(AudioOutput { sample_rate, queue })
+    }
+
+    pub fn push(&self, samples: &[f32]) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(samples);
+
+        let excess = queue.len().saturating_sub(MAX_QUEUED_SAMPLES);
+        queue.drain(..excess);
+    }
+}
+
+/// Write stereo samples to a host buffer of any channel count
+fn fill<T, N, C>(buffer: &mut [T], channels: usize, next: &mut N, convert: C)
+where
+    N: FnMut() -> f32,
+    C: Fn(f32) -> T,
+{
+    for frame in buffer.chunks_mut(channels) {
+        let (left, right) = (next(), next());
+        for (i, out) in frame.iter_mut().enumerate() {
+            *out = convert(if i % 2 == 0 { left } else { right });
+        }
+    }
+}
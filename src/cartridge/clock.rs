@@ -0,0 +1,57 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::apu::CPU_FREQUENCY;
+
+/// Time base of the cartridge real-time clocks (MBC3)
+pub trait Clock {
+    /// Current time, in seconds
+    fn now(&self) -> u64;
+
+    /// Called by the MMU with the T-cycles elapsed at normal speed
+    fn execute_cycle(&mut self, _time: u32) {}
+
+    /// `now` is a unix time, which the save file footers hold
+    fn is_unix_time(&self) -> bool {
+        true
+    }
+}
+
+/// Host time since the unix epoch, keeps running while the emulator is closed
+pub struct WallClock;
+
+impl Clock for WallClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// Time derived from the emulated cycles only, so replays and test runs
+/// see the exact same RTC values
+pub struct CycleClock {
+    start: u64,
+    cycles: u64,
+}
+
+impl CycleClock {
+    /// `start` is the time reported before the first cycle, in seconds
+    pub fn new(start: u64) -> Self {
+        CycleClock { start, cycles: 0 }
+    }
+}
+
+impl Clock for CycleClock {
+    fn now(&self) -> u64 {
+        self.start + self.cycles / CPU_FREQUENCY as u64
+    }
+
+    fn execute_cycle(&mut self, time: u32) {
+        self.cycles += time as u64;
+    }
+
+    fn is_unix_time(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+
+use super::get_ram_size;
+use crate::error::EmulatorError;
+
+/// https://gbdev.io/pandocs/The_Cartridge_Header.html#0143--cgb-flag
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CgbSupport {
+    None,
+    /// works on both DMG and CGB
+    Enhanced,
+    Only,
+}
+
+/// https://gbdev.io/pandocs/The_Cartridge_Header.html
+///
+/// 0100-0103  Entry point
+/// 0104-0133  Nintendo logo
+/// 0134-0143  Title (0134-013E on newer cartridges)
+/// 013F-0142  Manufacturer code
+/// 0143       CGB flag
+/// 0144-0145  New licensee code
+/// 0146       SGB flag
+/// 0147       Cartridge type
+/// 0148       ROM size
+/// 0149       RAM size
+/// 014A       Destination code
+/// 014B       Old licensee code
+/// 014C       Mask ROM version number
+/// 014D       Header checksum
+/// 014E-014F  Global checksum
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub japanese: bool,
+    pub old_licensee: u8,
+    /// only meaningful when the old licensee code is 0x33
+    pub new_licensee: Option<String>,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, EmulatorError> {
+        if rom.len() < 0x0150 {
+            return Err(EmulatorError::TruncatedRom(rom.len()));
+        }
+
+        let cgb = match rom[0x0143] {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+
+        // newer cartridges reuse the end of the title for the manufacturer code
+        let manufacturer = &rom[0x013F..=0x0142];
+        let manufacturer_code = match cgb {
+            CgbSupport::None => None,
+            _ if manufacturer.iter().all(u8::is_ascii_uppercase) => {
+                Some(String::from_utf8_lossy(manufacturer).into_owned())
+            }
+            _ => None,
+        };
+        let title_end = match (cgb, &manufacturer_code) {
+            (_, Some(_)) => 0x013F,
+            (CgbSupport::None, _) => 0x0144,
+            _ => 0x0143,
+        };
+        let title = rom[0x0134..title_end]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect::<String>();
+
+        let old_licensee = rom[0x014B];
+        let new_licensee = match old_licensee {
+            0x33 => Some(String::from_utf8_lossy(&rom[0x0144..=0x0145]).into_owned()),
+            _ => None,
+        };
+
+        Ok(CartridgeHeader {
+            title: title.trim_end().to_string(),
+            manufacturer_code,
+            cgb,
+            sgb: rom[0x0146] == 0x03 && old_licensee == 0x33,
+            cartridge_type: rom[0x0147],
+            rom_size: 0x8000 << rom[0x0148].min(8),
+            ram_size: get_ram_size(rom[0x0149]) as usize,
+            japanese: rom[0x014A] == 0x00,
+            old_licensee,
+            new_licensee,
+            version: rom[0x014C],
+            header_checksum: rom[0x014D],
+            global_checksum: u16::from_be_bytes([rom[0x014E], rom[0x014F]]),
+            computed_header_checksum: header_checksum(rom),
+            computed_global_checksum: global_checksum(rom),
+        })
+    }
+
+    /// Checked by the boot ROM, which locks up on a mismatch
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    /// Not checked by the hardware
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    /// https://gbdev.io/pandocs/The_Cartridge_Header.html#0147--cartridge-type
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => "unknown",
+        }
+    }
+}
+
+/// https://gbdev.io/pandocs/The_Cartridge_Header.html#014d--header-checksum
+fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x0134..=0x014C]
+        .iter()
+        .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// Sum of every byte of the ROM, except the checksum itself
+fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 0x014E && *i != 0x014F)
+        .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let checksum = |valid: bool| if valid { "ok" } else { "MISMATCH" };
+
+        writeln!(f, "Title:            {}", self.title)?;
+        if let Some(code) = &self.manufacturer_code {
+            writeln!(f, "Manufacturer:     {code}")?;
+        }
+        let cgb = match self.cgb {
+            CgbSupport::None => "no",
+            CgbSupport::Enhanced => "supported",
+            CgbSupport::Only => "required",
+        };
+        writeln!(f, "CGB:              {cgb}")?;
+        writeln!(
+            f,
+            "SGB:              {}",
+            if self.sgb { "yes" } else { "no" }
+        )?;
+        writeln!(
+            f,
+            "Cartridge type:   {:02X} ({})",
+            self.cartridge_type,
+            self.cartridge_type_name()
+        )?;
+        writeln!(f, "ROM size:         {} KiB", self.rom_size / 1024)?;
+        writeln!(f, "RAM size:         {} KiB", self.ram_size / 1024)?;
+        let destination = if self.japanese { "Japan" } else { "overseas" };
+        writeln!(f, "Destination:      {destination}")?;
+        match &self.new_licensee {
+            Some(code) => writeln!(f, "Licensee:         {code} (new)")?,
+            None => writeln!(f, "Licensee:         {:02X} (old)", self.old_licensee)?,
+        }
+        writeln!(f, "Version:          {}", self.version)?;
+        writeln!(
+            f,
+            "Header checksum:  {:02X} {}",
+            self.header_checksum,
+            checksum(self.header_checksum_valid())
+        )?;
+        if !self.header_checksum_valid() {
+            writeln!(
+                f,
+                "                  expected {:02X}",
+                self.computed_header_checksum
+            )?;
+        }
+        write!(
+            f,
+            "Global checksum:  {:04X} {}",
+            self.global_checksum,
+            checksum(self.global_checksum_valid())
+        )?;
+        if !self.global_checksum_valid() {
+            write!(
+                f,
+                "\n                  expected {:04X}",
+                self.computed_global_checksum
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_header() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0134..0x013F].copy_from_slice(b"POKEMON RED");
+    rom[0x013F..0x0143].copy_from_slice(b"APSE");
+    rom[0x0143] = 0x80;
+    rom[0x0144..0x0146].copy_from_slice(b"01");
+    rom[0x0146] = 0x03;
+    rom[0x0147] = 0x13;
+    rom[0x0149] = 0x03;
+    rom[0x014A] = 0x01;
+    rom[0x014B] = 0x33;
+    rom[0x014D] = header_checksum(&rom);
+
+    let header = CartridgeHeader::parse(&rom).unwrap();
+    assert_eq!(header.title, "POKEMON RED");
+    assert_eq!(header.manufacturer_code.as_deref(), Some("APSE"));
+    assert_eq!(header.cgb, CgbSupport::Enhanced);
+    assert!(header.sgb);
+    assert_eq!(header.cartridge_type_name(), "MBC3+RAM+BATTERY");
+    assert_eq!((header.rom_size, header.ram_size), (0x8000, 0x8000));
+    assert!(!header.japanese);
+    assert_eq!(header.new_licensee.as_deref(), Some("01"));
+    assert!(header.header_checksum_valid());
+    assert!(!header.global_checksum_valid());
+}
@@ -1,18 +1,19 @@
-use crate::cartridge::MBC;
+use crate::{cartridge::MBC, error::EmulatorError};
 
 pub struct MBC0 {
     rom: Vec<u8>,
 }
 
 impl MBC0 {
-    pub fn new(raw: Vec<u8>) -> Result<Self, &'static str> {
+    pub fn new(raw: Vec<u8>) -> Result<Self, EmulatorError> {
         Ok(MBC0 { rom: raw })
     }
 }
 
 impl MBC for MBC0 {
     fn read_rom(&self, addr: u16) -> u8 {
-        self.rom[addr as usize]
+        // ROMs smaller than 32 KiB leave the rest of the bus open
+        self.rom.get(addr as usize).copied().unwrap_or(0xFF)
     }
 
     fn read_ram(&self, _addr: u16) -> u8 {
@@ -26,4 +27,16 @@ impl MBC for MBC0 {
     fn write_ram(&mut self, _addr: u16, _data: u8) {
         ()
     }
+
+    fn rom_only(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn test_short_rom() {
+    let mbc = MBC0::new(vec![0x42; 0x150]).unwrap();
+    assert_eq!(mbc.read_rom(0x014F), 0x42);
+    assert_eq!(mbc.read_rom(0x0150), 0xFF);
+    assert_eq!(mbc.read_rom(0x7FFF), 0xFF);
 }
@@ -1,57 +1,43 @@
-use crate::cartridge::MBC;
-use std::{fs::File, io::Write, path::PathBuf};
+use crate::{cartridge::MBC, error::EmulatorError};
+use std::{io, path::PathBuf};
 
-use super::get_ram_size;
+use super::{get_ram_size, save::SaveRam};
 
 /// https://gbdev.io/pandocs/MBC1.html
 pub struct MBC1 {
     rom: Vec<u8>,
-    ram: Vec<u8>,
+    ram: SaveRam,
     rom_bank_idx: usize,
     ram_bank_idx: usize,
     ram_enabled: bool,
     ram_mode: bool,
-    save_file: Option<PathBuf>,
 }
 
 impl MBC1 {
-    pub fn new(raw: Vec<u8>, path: PathBuf) -> Result<Self, &'static str> {
+    pub fn new(raw: Vec<u8>, path: PathBuf) -> Result<Self, EmulatorError> {
         let (save_file, ram_size) = match raw[0x0147] {
             0x02 => (None, get_ram_size(raw[0x0149])),
             0x03 => (Some(path.with_extension("save")), get_ram_size(raw[0x0149])),
             _ => (None, 0),
         };
 
-        let mut mbc = MBC1 {
+        let mut ram = SaveRam::new(ram_size as usize, save_file);
+        ram.load(&[])?;
+
+        Ok(MBC1 {
             rom: raw,
-            ram: vec![0; ram_size as usize],
+            ram,
             rom_bank_idx: 1,
             ram_bank_idx: 0,
             ram_enabled: false,
             ram_mode: false,
-            save_file,
-        };
-
-        mbc.load_save_file();
-
-        Ok(mbc)
+        })
     }
 
-    fn load_save_file(&mut self) {
-        match &self.save_file {
-            Some(path) => {
-                match File::open(path) {
-                    Ok(mut f) => {
-                        f.write_all(&self.ram)
-                            .expect("Error loading save file. Save file corrupt?");
-                    }
-                    Err(_) => {
-                        dbg!("Unable to open save file");
-                    }
-                };
-            }
-            None => (),
-        }
+    /// The RAM bank register is only honored in RAM banking mode
+    fn ram_index(&self, addr: u16) -> usize {
+        let bank = if self.ram_mode { self.ram_bank_idx } else { 0 };
+        bank * 0x2000 + (addr as usize - 0xA000)
     }
 }
 
@@ -68,13 +54,10 @@ impl MBC for MBC1 {
 
     fn read_ram(&self, addr: u16) -> u8 {
         if !self.ram_enabled {
-            return 0;
+            return 0xFF;
         }
 
-        *self
-            .ram
-            .get(self.ram_bank_idx * 0x2000 + (addr as usize - 0x2000))
-            .unwrap_or(&0)
+        self.ram.read(self.ram_index(addr))
     }
 
     fn write_rom(&mut self, addr: u16, data: u8) {
@@ -95,7 +78,7 @@ impl MBC for MBC1 {
                 }
             }
             0x6000..=0x7FFF => self.ram_mode = data & 0b1 == 1,
-            _ => panic!("Cannot write to {addr:04x} - MBC1"),
+            _ => (),
         };
     }
 
@@ -104,24 +87,10 @@ impl MBC for MBC1 {
             return;
         }
 
-        let bank = if self.ram_mode { self.ram_bank_idx } else { 0 };
-        let index = bank * 0x2000 + (addr as usize - 0x2000);
-
-        self.ram[index] = data;
+        self.ram.write(self.ram_index(addr), data);
     }
-}
 
-/// auto save when drop CPU
-impl Drop for MBC1 {
-    fn drop(&mut self) {
-        match &self.save_file {
-            None => (),
-            Some(path) => {
-                let mut save_file = File::create(path).expect("Cannot create save file at {path}");
-                save_file
-                    .write_all(&self.ram)
-                    .expect("Cannot write to save file at {path}");
-            }
-        }
+    fn flush(&mut self) -> io::Result<()> {
+        self.ram.save(&[])
     }
 }
@@ -1,53 +1,34 @@
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{io, path::PathBuf};
 
-use crate::cartridge::MBC;
+use super::save::SaveRam;
+use crate::{cartridge::MBC, error::EmulatorError};
 
 /// https://gbdev.io/pandocs/MBC2.html
 pub struct MBC2 {
     rom: Vec<u8>,
-    ram: Vec<u8>,
+    /// 512 half-bytes, stored in the lower nibble
+    ram: SaveRam,
     rom_bank_idx: usize,
     ram_enabled: bool,
-    save_file: PathBuf,
 }
 
 impl MBC2 {
-    pub fn new(raw: Vec<u8>, path: PathBuf) -> Result<Self, &'static str> {
-        let save_file = path.with_extension("save");
-        let mut mbc = MBC2 {
-            rom: raw,
-            ram: vec![0; 0x200],
-            rom_bank_idx: 1,
-            ram_enabled: false,
-            save_file,
+    pub fn new(raw: Vec<u8>, path: PathBuf) -> Result<Self, EmulatorError> {
+        // only MBC2+BATTERY keeps its RAM
+        let save_file = match raw[0x0147] {
+            0x06 => Some(path.with_extension("save")),
+            _ => None,
         };
 
-        mbc.load_save_file();
+        let mut ram = SaveRam::new(0x200, save_file);
+        ram.load(&[])?;
 
-        Ok(mbc)
-    }
-
-    fn load_save_file(&mut self) {
-        match File::open(&self.save_file) {
-            Ok(mut f) => {
-                f.write_all(&self.ram)
-                    .expect("Error loading save file. Save file corrupt?");
-            }
-            Err(_) => {
-                dbg!("Unable to open save file");
-            }
-        };
-    }
-}
-
-/// auto save when drop CPU
-impl Drop for MBC2 {
-    fn drop(&mut self) {
-        let mut save_file =
-            File::create(&self.save_file).expect("Cannot create save file at {path}");
-        save_file
-            .write_all(&self.ram)
-            .expect("Cannot write to save file at {path}");
+        Ok(MBC2 {
+            rom: raw,
+            ram,
+            rom_bank_idx: 1,
+            ram_enabled: false,
+        })
     }
 }
 
@@ -63,23 +44,24 @@ impl MBC for MBC2 {
     }
 
     fn read_ram(&self, addr: u16) -> u8 {
-        let index = (addr as usize - 0xA000) % 0x0200;
+        if !self.ram_enabled {
+            return 0xFF;
+        }
 
-        let res = *self.rom.get(index).unwrap_or(&0);
-        res & 0x0F
+        let index = (addr as usize - 0xA000) % 0x0200;
+        0xF0 | self.ram.read(index)
     }
 
     fn write_rom(&mut self, addr: u16, data: u8) {
-        match addr {
-            0x0000..=0x3FFF => {
-                if addr & 0x0100 == 0 {
-                    self.ram_enabled = (data & 0x0F) == 0x0A;
-                } else {
-                    self.rom_bank_idx = (data as usize & 0x0F).max(1)
-                }
-            }
-            0x4000..=0x7FFF => panic!("Trying to write to read-only {addr:04x} - MBC2"),
-            _ => panic!("Cannot write to {addr:04x} - MBC2"),
+        // no register is mapped to 0x4000..=0x7FFF, the write goes nowhere
+        if addr > 0x3FFF {
+            return;
+        }
+
+        if addr & 0x0100 == 0 {
+            self.ram_enabled = (data & 0x0F) == 0x0A;
+        } else {
+            self.rom_bank_idx = (data as usize & 0x0F).max(1)
         }
     }
 
@@ -88,6 +70,10 @@ impl MBC for MBC2 {
             return;
         }
         let index = (addr as usize - 0xA000) % 0x0200;
-        self.ram[index] = data & 0x0F;
+        self.ram.write(index, data & 0x0F);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.ram.save(&[])
     }
 }
@@ -1,10 +1,13 @@
-use super::get_ram_size;
-use crate::cartridge::MBC;
-use std::{
-    fs::File,
-    io::{Read, Write},
-    path::PathBuf,
+use super::{
+    clock::Clock,
+    get_ram_size,
+    save::SaveRam,
+    save_format::{RtcFooter, RtcState},
 };
+use crate::{cartridge::MBC, error::EmulatorError};
+use std::{io, path::PathBuf};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
 
 /// $08  RTC S   Seconds   0-59 ($00-$3B)
 /// $09  RTC M   Minutes   0-59 ($00-$3B)
@@ -14,6 +17,7 @@ use std::{
 ///       Bit 0  Most significant bit of Day Counter (Bit 8)
 ///       Bit 6  Halt (0=Active, 1=Stop Timer)
 ///       Bit 7  Day Counter Carry Bit (1=Counter Overflow)
+#[derive(Clone, Copy, PartialEq, Debug)]
 struct RTCRegister {
     pub sec: u8,
     pub min: u8,
@@ -40,25 +44,86 @@ impl RTCRegister {
         self.day_low = source.day_low;
         self.day_high = source.day_high;
     }
+
+    pub fn halted(&self) -> bool {
+        self.day_high & 0b0100_0000 != 0
+    }
+
+    /// Move the clock forward, setting the carry bit when the 9-bit day counter overflows
+    pub fn advance(&mut self, seconds: u64) {
+        let days = ((self.day_high as u64 & 0b1) << 8) | self.day_low as u64;
+        let total = self.sec as u64
+            + self.min as u64 * 60
+            + self.hour as u64 * 3600
+            + days * SECONDS_PER_DAY
+            + seconds;
+
+        let days = total / SECONDS_PER_DAY;
+        if days > 0x1FF {
+            self.day_high |= 0b1000_0000;
+        }
+
+        self.sec = (total % 60) as u8;
+        self.min = (total / 60 % 60) as u8;
+        self.hour = (total / 3600 % 24) as u8;
+        self.day_low = days as u8;
+        self.day_high = (self.day_high & 0b1100_0000) | ((days >> 8) & 0b1) as u8;
+    }
+
+    /// `reg` is the RAM bank number, 0x08..=0x0C
+    pub fn read(&self, reg: usize) -> u8 {
+        match reg {
+            0x08 => self.sec,
+            0x09 => self.min,
+            0x0A => self.hour,
+            0x0B => self.day_low,
+            _ => self.day_high,
+        }
+    }
+
+    pub fn write(&mut self, reg: usize, data: u8) {
+        match reg {
+            0x08 => self.sec = data & 0b0011_1111,
+            0x09 => self.min = data & 0b0011_1111,
+            0x0A => self.hour = data & 0b0001_1111,
+            0x0B => self.day_low = data,
+            _ => self.day_high = data & 0b1100_0001,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 5] {
+        [self.sec, self.min, self.hour, self.day_low, self.day_high]
+    }
+
+    fn from_bytes(regs: [u8; 5]) -> Self {
+        let mut rtc = RTCRegister::new();
+        for (reg, data) in (0x08..=0x0C).zip(regs) {
+            rtc.write(reg, data);
+        }
+        rtc
+    }
 }
 
 /// https://gbdev.io/pandocs/MBC3.html
 pub struct MBC3 {
     rom: Vec<u8>,
-    ram: Vec<u8>,
+    ram: SaveRam,
     rom_bank_idx: usize,
+    /// 0x00..=0x03 maps a RAM bank, 0x08..=0x0C a RTC register
     ram_bank_idx: usize,
     ram_enabled: bool,
-    ram_mode: bool,
+    has_rtc: bool,
     rtc_reg: RTCRegister,
     rtc_reg_latch: RTCRegister,
-    rtc_result: Option<u64>,
-    rtc_halt: bool,
-    save_file: Option<PathBuf>,
+    clock: Box<dyn Clock>,
+    /// time (in seconds, as given by `clock`) up to which `rtc_reg` has been advanced
+    rtc_timestamp: u64,
+    /// last write to 0x6000..=0x7FFF was 0x00, a 0x01 now latches the clock
+    rtc_latch_ready: bool,
 }
 
 impl MBC3 {
-    pub fn new(raw: Vec<u8>, path: PathBuf) -> Result<Self, &'static str> {
+    pub fn new(raw: Vec<u8>, path: PathBuf, clock: Box<dyn Clock>) -> Result<Self, EmulatorError> {
         let subtype = raw[0x0147];
 
         let save_file = match subtype {
@@ -71,83 +136,79 @@ impl MBC3 {
             _ => 0,
         };
 
-        let rtc_result = match subtype {
-            0x0F | 0x10 => Some(0),
-            _ => None,
-        };
+        let mut ram = SaveRam::new(ram_size as usize, save_file);
+        let footer = ram.load(&RtcFooter::SIZES)?;
 
         let mut mbc = MBC3 {
             rom: raw,
-            ram: vec![0; ram_size as usize],
+            ram,
             rom_bank_idx: 1,
             ram_bank_idx: 0,
             ram_enabled: false,
-            ram_mode: false,
+            has_rtc: matches!(subtype, 0x0F | 0x10),
             rtc_reg: RTCRegister::new(),
             rtc_reg_latch: RTCRegister::new(),
-            rtc_result,
-            rtc_halt: false,
-            save_file,
+            // a clock with its own epoch starts the RTC at its initial time
+            rtc_timestamp: if clock.is_unix_time() { clock.now() } else { 0 },
+            clock,
+            rtc_latch_ready: false,
         };
 
-        mbc.load_save_file()?;
+        // the save file is the raw RAM, followed by the RTC footer for cartridges with a timer
+        if mbc.has_rtc && !footer.is_empty() {
+            mbc.load_rtc_footer(&footer);
+        }
 
         Ok(mbc)
     }
 
-    fn load_save_file(&mut self) -> Result<(), &'static str> {
-        match &self.save_file {
-            None => Ok(()),
-            Some(path) => {
-                let mut file = match File::open(path) {
-                    Ok(f) => f,
-                    Err(_) => {
-                        dbg!("Unable to open save file");
-                        return Ok(());
-                    }
-                };
-
-                let mut data = vec![];
-                match file.read_to_end(&mut data) {
-                    Err(..) => Err("Could not read save file"),
-                    Ok(..) => {
-                        self.ram = data;
-                        Ok(())
-                    }
-                }
-            }
+    fn load_rtc_footer(&mut self, footer: &[u8]) {
+        if let Some(rtc) = RtcState::decode(footer) {
+            self.rtc_reg = RTCRegister::from_bytes(rtc.regs);
+            self.rtc_reg_latch = RTCRegister::from_bytes(rtc.latched);
+            // a clock with its own epoch resumes from the saved registers,
+            // and so does any clock when the save time is unknown
+            self.rtc_timestamp = if self.clock.is_unix_time() && rtc.timestamp != 0 {
+                rtc.timestamp
+            } else {
+                self.clock.now()
+            };
         }
-    }
 
-    fn latch_clock_data(&mut self) {
-        unimplemented!("Latch RTC for MBC3");
+        // time went on while the emulator was closed
         self.calc_rtc_reg();
-        self.rtc_reg_latch.copy_from(&self.rtc_reg);
     }
 
-    fn calc_rtc_reg(&mut self) {
-        unimplemented!("Latch RTC for MBC3");
-        todo!()
+    fn rtc_footer(&mut self) -> Vec<u8> {
+        self.calc_rtc_reg();
+
+        // the footer holds a unix time. The cycle clock has none, it writes 0
+        // (unknown) so that the same run always gives the same save file
+        let timestamp = if self.clock.is_unix_time() {
+            self.rtc_timestamp
+        } else {
+            0
+        };
+        RtcState {
+            regs: self.rtc_reg.to_bytes(),
+            latched: self.rtc_reg_latch.to_bytes(),
+            timestamp,
+        }
+        .encode(RtcFooter::Bytes48)
     }
 
-    fn calc_rtc_result(&mut self) {
-        unimplemented!("Latch RTC for MBC3");
-        todo!()
+    fn latch_clock_data(&mut self) {
+        self.calc_rtc_reg();
+        self.rtc_reg_latch.copy_from(&self.rtc_reg);
     }
-}
 
-/// auto save when drop CPU
-impl Drop for MBC3 {
-    fn drop(&mut self) {
-        match &self.save_file {
-            None => (),
-            Some(path) => {
-                let mut save_file = File::create(path).expect("Cannot create save file at {path}");
-                save_file
-                    .write_all(&self.ram)
-                    .expect("Cannot write to save file at {path}");
-            }
+    /// Bring the RTC registers up to date with the host clock
+    fn calc_rtc_reg(&mut self) {
+        let now = self.clock.now();
+        if !self.rtc_reg.halted() {
+            self.rtc_reg.advance(now.saturating_sub(self.rtc_timestamp));
         }
+        self.rtc_timestamp = now;
     }
 }
 
@@ -164,22 +225,30 @@ impl MBC for MBC3 {
 
     fn read_ram(&self, addr: u16) -> u8 {
         if !self.ram_enabled {
-            return 0;
+            return 0xFF;
         }
 
-        *self
-            .ram
-            .get(self.ram_bank_idx * 0x0200 + (addr as usize - 0x2000))
-            .unwrap_or(&0)
+        match self.ram_bank_idx {
+            0x00..=0x03 => self
+                .ram
+                .read(self.ram_bank_idx * 0x2000 + (addr as usize - 0xA000)),
+            0x08..=0x0C if self.has_rtc => self.rtc_reg_latch.read(self.ram_bank_idx),
+            _ => 0xFF,
+        }
     }
 
     fn write_rom(&mut self, addr: u16, data: u8) {
         match addr {
-            0x0000..=0x1FFF => self.ram_enabled = data == 0x0A,
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
             0x2000..=0x3FFF => self.rom_bank_idx = (data as usize & 0b0111_1111).max(1),
-            0x4000..=0x5FFF => self.ram_bank_idx = data as usize,
-            0x6000..=0x7FFF => unimplemented!("Latch RTC for MBC3"),
-            _ => panic!("Cannot write to {addr:04x} - MBC3"),
+            0x4000..=0x5FFF => self.ram_bank_idx = data as usize & 0x0F,
+            0x6000..=0x7FFF => {
+                if self.rtc_latch_ready && data == 0x01 && self.has_rtc {
+                    self.latch_clock_data();
+                }
+                self.rtc_latch_ready = data == 0x00;
+            }
+            _ => (),
         }
     }
 
@@ -188,13 +257,132 @@ impl MBC for MBC3 {
             return;
         }
 
-        if self.ram_bank_idx <= 3 {
-            let bank = if self.ram_mode { self.ram_bank_idx } else { 0 };
-            let index = bank * 0x2000 + (addr as usize - 0x2000);
+        match self.ram_bank_idx {
+            0x00..=0x03 => {
+                let index = self.ram_bank_idx * 0x2000 + (addr as usize - 0xA000);
+                self.ram.write(index, data);
+            }
+            0x08..=0x0C if self.has_rtc => {
+                // apply the time elapsed so far with the previous halt state
+                self.calc_rtc_reg();
+                self.rtc_reg.write(self.ram_bank_idx, data);
+                self.ram.mark_dirty();
+            }
+            _ => (),
+        }
+    }
+
+    fn execute_cycle(&mut self, time: u32) {
+        self.clock.execute_cycle(time);
+    }
 
-            self.ram[index] = data;
+    fn flush(&mut self) -> io::Result<()> {
+        if self.has_rtc {
+            // the clock keeps running, its state is always worth saving
+            let footer = self.rtc_footer();
+            self.ram.mark_dirty();
+            self.ram.save(&footer)
         } else {
-            unimplemented!("Latch RTC for MBC3");
+            self.ram.save(&[])
         }
     }
 }
+
+#[test]
+fn test_rtc_advance() {
+    let mut rtc = RTCRegister::new();
+    rtc.advance(SECONDS_PER_DAY * 300 + 3661);
+    assert_eq!((rtc.sec, rtc.min, rtc.hour), (1, 1, 1));
+    assert_eq!((rtc.day_low, rtc.day_high), (44, 0b1));
+
+    rtc.advance(SECONDS_PER_DAY * 212);
+    assert_eq!((rtc.day_low, rtc.day_high), (0, 0b1000_0000));
+}
+
+#[test]
+fn test_rtc_cycle_clock() {
+    use super::clock::CycleClock;
+    use crate::apu::CPU_FREQUENCY;
+
+    let mut rom = vec![0; 0x8000];
+    rom[0x0147] = 0x0F;
+    let path = std::env::temp_dir().join("gb_emulator_test_rtc_cycle_clock.gb");
+    let _ = std::fs::remove_file(path.with_extension("save"));
+
+    let mut mbc = MBC3::new(rom, path.clone(), Box::new(CycleClock::new(0))).unwrap();
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_rom(0x4000, 0x08);
+
+    mbc.execute_cycle(CPU_FREQUENCY * 2);
+    assert_eq!(mbc.read_ram(0xA000), 0);
+
+    mbc.write_rom(0x6000, 0x00);
+    mbc.write_rom(0x6000, 0x01);
+    assert_eq!(mbc.read_ram(0xA000), 2);
+
+    // halted: time doesn't move
+    mbc.write_rom(0x4000, 0x0C);
+    mbc.write_ram(0xA000, 0b0100_0000);
+    mbc.execute_cycle(CPU_FREQUENCY * 5);
+    mbc.write_rom(0x6000, 0x00);
+    mbc.write_rom(0x6000, 0x01);
+    mbc.write_rom(0x4000, 0x08);
+    assert_eq!(mbc.read_ram(0xA000), 2);
+
+    mbc.flush().unwrap();
+    let _ = std::fs::remove_file(path.with_extension("save"));
+}
+
+#[test]
+fn test_rtc_cycle_clock_save() {
+    use super::clock::{CycleClock, WallClock};
+    use crate::apu::CPU_FREQUENCY;
+
+    let mut rom = vec![0; 0x8000];
+    rom[0x0147] = 0x0F;
+    let path = std::env::temp_dir().join("gb_emulator_test_rtc_cycle_clock_save.gb");
+    let save_path = path.with_extension("save");
+
+    let latch = |mbc: &mut MBC3| {
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        (0x08..=0x0C)
+            .map(|reg| {
+                mbc.write_rom(0x4000, reg);
+                mbc.read_ram(0xA000)
+            })
+            .collect::<Vec<u8>>()
+    };
+
+    // the RTC starts at the time given to the clock: day 1, 01:00:00
+    let run = || {
+        let _ = std::fs::remove_file(&save_path);
+        let clock = CycleClock::new(SECONDS_PER_DAY + 3600);
+        let mut mbc = MBC3::new(rom.clone(), path.clone(), Box::new(clock)).unwrap();
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(latch(&mut mbc), [0, 0, 1, 1, 0]);
+        mbc.execute_cycle(CPU_FREQUENCY * 75);
+        assert_eq!(latch(&mut mbc), [15, 1, 1, 1, 0]);
+        mbc.flush().unwrap();
+        std::fs::read(&save_path).unwrap()
+    };
+    let save = run();
+    assert_eq!(run(), save);
+    // no save time
+    assert_eq!(save[save.len() - 8..], [0; 8]);
+
+    // another cycle clock resumes from the saved registers, whatever its start
+    let mut mbc = MBC3::new(rom.clone(), path.clone(), Box::new(CycleClock::new(0))).unwrap();
+    mbc.write_rom(0x0000, 0x0A);
+    assert_eq!(latch(&mut mbc), [15, 1, 1, 1, 0]);
+    mbc.execute_cycle(CPU_FREQUENCY * 5);
+    assert_eq!(latch(&mut mbc), [20, 1, 1, 1, 0]);
+    drop(mbc);
+
+    // so does the host clock, instead of counting the time since 1970
+    let mbc = MBC3::new(rom, path, Box::new(WallClock)).unwrap();
+    assert_eq!(mbc.rtc_reg.to_bytes(), [15, 1, 1, 1, 0]);
+    drop(mbc);
+
+    std::fs::remove_file(save_path).unwrap();
+}
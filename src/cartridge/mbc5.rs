@@ -0,0 +1,196 @@
+use super::{get_ram_size, save::SaveRam};
+use crate::{cartridge::MBC, error::EmulatorError};
+use std::{io, path::PathBuf};
+
+/// https://gbdev.io/pandocs/MBC5.html
+pub struct MBC5 {
+    rom: Vec<u8>,
+    ram: SaveRam,
+    rom_bank_idx: usize,
+    ram_bank_idx: usize,
+    ram_enabled: bool,
+    /// cartridge types 0x1C..=0x1E, bit 3 of the RAM bank register drives the motor
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl MBC5 {
+    pub fn new(raw: Vec<u8>, path: PathBuf) -> Result<Self, EmulatorError> {
+        let subtype = raw[0x0147];
+
+        let save_file = match subtype {
+            0x1B | 0x1E => Some(path.with_extension("save")),
+            _ => None,
+        };
+
+        let ram_size = match subtype {
+            0x1A | 0x1B | 0x1D | 0x1E => get_ram_size(raw[0x0149]),
+            _ => 0,
+        };
+
+        let mut ram = SaveRam::new(ram_size as usize, save_file);
+        ram.load(&[])?;
+
+        Ok(MBC5 {
+            rom: raw,
+            ram,
+            rom_bank_idx: 1,
+            ram_bank_idx: 0,
+            ram_enabled: false,
+            has_rumble: (0x1C..=0x1E).contains(&subtype),
+            rumble: false,
+        })
+    }
+}
+
+impl MBC for MBC5 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let index = match addr {
+            0x0000..=0x3FFF => addr as usize,
+            0x4000..=0x7FFF => {
+                let bank_count = (self.rom.len() / 0x4000).max(1);
+                (self.rom_bank_idx % bank_count) * 0x4000 + (addr as usize - 0x4000)
+            }
+            _ => return 0,
+        };
+
+        *self.rom.get(index).unwrap_or(&0)
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        let index = self.ram_bank_idx * 0x2000 + (addr as usize - 0xA000);
+        self.ram.read(index % self.ram.len())
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = data == 0x0A,
+            // unlike MBC1 and MBC3, bank 0 can be mapped to 0x4000..=0x7FFF
+            0x2000..=0x2FFF => self.rom_bank_idx = (self.rom_bank_idx & 0x100) | data as usize,
+            0x3000..=0x3FFF => {
+                self.rom_bank_idx = (self.rom_bank_idx & 0xFF) | ((data as usize & 0b1) << 8)
+            }
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = data & 0b0000_1000 != 0;
+                    self.ram_bank_idx = data as usize & 0b0000_0111;
+                } else {
+                    self.ram_bank_idx = data as usize & 0b0000_1111;
+                }
+            }
+            0x6000..=0x7FFF => (),
+            _ => (),
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, data: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+
+        let index = self.ram_bank_idx * 0x2000 + (addr as usize - 0xA000);
+        let len = self.ram.len();
+        self.ram.write(index % len, data);
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.ram.save(&[])
+    }
+}
+
+#[cfg(test)]
+fn test_cartridge(subtype: u8, ram_size: u8, banks: usize) -> MBC5 {
+    let mut rom = vec![0; banks * 0x4000];
+    // every bank starts with its number
+    for bank in 0..banks {
+        rom[bank * 0x4000..bank * 0x4000 + 2].copy_from_slice(&(bank as u16).to_le_bytes());
+    }
+    rom[0x0147] = subtype;
+    rom[0x0149] = ram_size;
+    MBC5::new(rom, PathBuf::from("mbc5_test.gb")).unwrap()
+}
+
+#[cfg(test)]
+fn rom_bank(mbc: &MBC5) -> u16 {
+    u16::from_le_bytes([mbc.read_rom(0x4000), mbc.read_rom(0x4001)])
+}
+
+#[test]
+fn test_rom_banks() {
+    let mut mbc = test_cartridge(0x19, 0x00, 512);
+    assert_eq!(rom_bank(&mbc), 1);
+
+    mbc.write_rom(0x2000, 0x42);
+    assert_eq!(rom_bank(&mbc), 0x42);
+    mbc.write_rom(0x3000, 0x01);
+    assert_eq!(rom_bank(&mbc), 0x142);
+    // the low byte keeps bit 8
+    mbc.write_rom(0x2000, 0x05);
+    assert_eq!(rom_bank(&mbc), 0x105);
+    mbc.write_rom(0x3000, 0xFE);
+    assert_eq!(rom_bank(&mbc), 0x05);
+
+    mbc.write_rom(0x2000, 0x00);
+    assert_eq!(rom_bank(&mbc), 0);
+
+    // wraps around smaller ROMs
+    let mut mbc = test_cartridge(0x19, 0x00, 64);
+    mbc.write_rom(0x2000, 0x41);
+    assert_eq!(rom_bank(&mbc), 1);
+}
+
+#[test]
+fn test_ram_banks() {
+    // 32 KiB: 4 banks
+    let mut mbc = test_cartridge(0x1A, 0x03, 2);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    mbc.write_rom(0x0000, 0x0A);
+
+    for bank in 0..4 {
+        mbc.write_rom(0x4000, bank);
+        mbc.write_ram(0xA000, 0x10 + bank);
+    }
+    mbc.write_rom(0x4000, 2);
+    assert_eq!(mbc.read_ram(0xA000), 0x12);
+    // banks past the RAM size mirror the existing ones
+    mbc.write_rom(0x4000, 6);
+    assert_eq!(mbc.read_ram(0xA000), 0x12);
+    mbc.write_rom(0x4000, 0x0F);
+    assert_eq!(mbc.read_ram(0xA000), 0x13);
+
+    mbc.write_rom(0x0000, 0x00);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+}
+
+#[test]
+fn test_rumble() {
+    let mut mbc = test_cartridge(0x1D, 0x04, 2);
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_rom(0x4000, 0x01);
+    mbc.write_ram(0xA000, 0x55);
+    assert!(!mbc.rumble());
+
+    // bit 3 turns the motor on and doesn't select bank 9
+    mbc.write_rom(0x4000, 0x09);
+    assert!(mbc.rumble());
+    assert_eq!(mbc.read_ram(0xA000), 0x55);
+    mbc.write_rom(0x4000, 0x01);
+    assert!(!mbc.rumble());
+
+    // without a motor, bit 3 is part of the bank number
+    let mut mbc = test_cartridge(0x1A, 0x04, 2);
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_rom(0x4000, 0x09);
+    mbc.write_ram(0xA000, 0x99);
+    mbc.write_rom(0x4000, 0x01);
+    assert_eq!(mbc.read_ram(0xA000), 0x00);
+    assert!(!mbc.rumble());
+}
@@ -1,15 +1,25 @@
+pub mod clock;
+pub mod header;
 mod mbc0;
 mod mbc1;
 mod mbc2;
 mod mbc3;
+mod mbc5;
+mod save;
+pub mod save_format;
 
+use std::io;
 use std::path::PathBuf;
 use std::{fs::File, io::Read};
 
+use self::clock::Clock;
+use self::header::CartridgeHeader;
 use self::mbc0::MBC0;
 use self::mbc1::MBC1;
 use self::mbc2::MBC2;
 use self::mbc3::MBC3;
+use self::mbc5::MBC5;
+use crate::error::EmulatorError;
 
 pub trait MBC {
     // a ROM bank size is 0x4000
@@ -17,27 +27,45 @@ pub trait MBC {
     fn read_ram(&self, addr: u16) -> u8;
     fn write_rom(&mut self, addr: u16, data: u8);
     fn write_ram(&mut self, addr: u16, data: u8);
+
+    /// Advance the time-keeping hardware by `time` T-cycles at normal speed
+    fn execute_cycle(&mut self, _time: u32) {}
+
+    /// No mapper nor RAM: writes to the cartridge and reads of
+    /// 0xA000..=0xBFFF reach nothing
+    fn rom_only(&self) -> bool {
+        false
+    }
+
+    /// State of the rumble motor, for cartridges that have one
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// Write the battery backed RAM (and RTC) to the save file, if any
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 /// Receive a path and return the correct MBC type,
-/// or error if unrecognized
-pub fn get_mbc(path: PathBuf) -> Result<Box<dyn MBC + 'static>, &'static str> {
+/// or error if unrecognized. `clock` is the time base of cartridges with a RTC
+pub fn get_mbc(
+    path: PathBuf,
+    clock: Box<dyn Clock>,
+) -> Result<Box<dyn MBC + 'static>, EmulatorError> {
     let mut data: Vec<u8> = vec![];
-    File::open(&path)
-        .and_then(|mut f| f.read_to_end(&mut data))
-        .map_err(|_| "Could not read ROM")?;
+    File::open(&path).and_then(|mut f| f.read_to_end(&mut data))?;
 
-    if data.len() < 0x0148 {
-        return Err("ROM is too small");
-    }
+    let header = CartridgeHeader::parse(&data)?;
 
-    match data[0x0147] {
+    match header.cartridge_type {
         0x00 => Ok(Box::new(MBC0::new(data)?)),
         0x01..=0x03 => Ok(Box::new(MBC1::new(data, path)?)),
         0x05..=0x06 => Ok(Box::new(MBC2::new(data, path)?)),
-        0x0F..=0x13 => Ok(Box::new(MBC3::new(data, path)?)),
-        0x19..=0x1E => unimplemented!("MBC5"),
-        _ => todo!("MBC format not supported. Only support MBC0, 1, 2, 3 and 5"),
+        0x0F..=0x13 => Ok(Box::new(MBC3::new(data, path, clock)?)),
+        0x19..=0x1E => Ok(Box::new(MBC5::new(data, path)?)),
+        byte => Err(EmulatorError::UnsupportedMapper(byte)),
     }
 }
 
@@ -47,7 +75,7 @@ pub fn get_ram_size(byte_0149: u8) -> u32 {
         0x02 => 0x2000,
         0x03 => 0x8000,
         0x04 => 0x20000,
-        0x05 => 0x4000,
+        0x05 => 0x10000,
         _ => 0,
     }
 }
@@ -0,0 +1,127 @@
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read, Write},
+    path::PathBuf,
+};
+
+use crate::error::EmulatorError;
+
+/// Cartridge RAM, backed by a `.save` file when the cartridge has a battery
+///
+/// The save file holds the raw RAM, optionally followed by a mapper specific
+/// footer (the MBC3 RTC state). Writes go to a temporary file which is then
+/// renamed over the save file, so a crash never leaves a truncated save behind.
+pub struct SaveRam {
+    ram: Vec<u8>,
+    save_file: Option<PathBuf>,
+    /// changed since it was last loaded or saved
+    dirty: bool,
+}
+
+impl SaveRam {
+    pub fn new(size: usize, save_file: Option<PathBuf>) -> Self {
+        SaveRam {
+            ram: vec![0; size],
+            save_file,
+            dirty: false,
+        }
+    }
+
+    /// Fill the RAM from the save file and return the footer that followed it.
+    /// A missing save file is not an error, the RAM is left blank.
+    /// `footer_sizes` lists the accepted footer lengths besides 0.
+    pub fn load(&mut self, footer_sizes: &[usize]) -> Result<Vec<u8>, EmulatorError> {
+        let path = match &self.save_file {
+            Some(path) => path,
+            None => return Ok(vec![]),
+        };
+
+        let mut data = vec![];
+        match File::open(path) {
+            Ok(mut f) => f.read_to_end(&mut data)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let footer_size = data.len().wrapping_sub(self.ram.len());
+        if data.len() < self.ram.len() || (footer_size != 0 && !footer_sizes.contains(&footer_size))
+        {
+            return Err(EmulatorError::BadSave(format!(
+                "{} bytes, expected {} bytes of RAM",
+                data.len(),
+                self.ram.len()
+            )));
+        }
+
+        let footer = data.split_off(self.ram.len());
+        self.ram = data;
+        self.dirty = false;
+        Ok(footer)
+    }
+
+    /// Atomically write the RAM followed by `footer` to the save file,
+    /// if it changed since the last save
+    pub fn save(&mut self, footer: &[u8]) -> io::Result<()> {
+        let path = match &self.save_file {
+            Some(path) if self.dirty => path,
+            _ => return Ok(()),
+        };
+
+        let tmp_path = path.with_extension("save.tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&self.ram)?;
+        tmp.write_all(footer)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn len(&self) -> usize {
+        self.ram.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ram.is_empty()
+    }
+
+    /// Out of range reads return 0xFF, like an unconnected bus
+    pub fn read(&self, index: usize) -> u8 {
+        *self.ram.get(index).unwrap_or(&0xFF)
+    }
+
+    pub fn write(&mut self, index: usize, data: u8) {
+        if let Some(byte) = self.ram.get_mut(index) {
+            if *byte != data {
+                *byte = data;
+                self.dirty = true;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_save_and_load() {
+    let path = std::env::temp_dir().join("gb_emulator_test_save_and_load.save");
+
+    let mut ram = SaveRam::new(0x2000, Some(path.clone()));
+    ram.write(0x10, 0x42);
+    assert!(ram.dirty);
+    ram.save(&[1, 2, 3]).unwrap();
+    assert!(!ram.dirty);
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0x2003);
+
+    let mut loaded = SaveRam::new(0x2000, Some(path.clone()));
+    assert_eq!(loaded.load(&[3]).unwrap(), vec![1, 2, 3]);
+    assert_eq!(loaded.read(0x10), 0x42);
+
+    let mut wrong_size = SaveRam::new(0x8000, Some(path.clone()));
+    assert!(wrong_size.load(&[]).is_err());
+
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,232 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    str::FromStr,
+};
+
+/// Cartridge RAM sizes found in the wild, 0x200 is the MBC2 built-in RAM
+/// and 0 a MBC3 with a RTC but no RAM
+const RAM_SIZES: [usize; 7] = [0, 0x200, 0x800, 0x2000, 0x8000, 0x10000, 0x20000];
+
+/// Layout of the MBC3 RTC state appended after the RAM
+/// https://bgb.bircd.org/rtcsave.html
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RtcFooter {
+    /// current registers, 4 reserved bytes and a 64-bit timestamp, without the latched registers
+    Bytes32,
+    /// current and latched registers as u32 and a 32-bit timestamp (older VBA-M, BGB)
+    Bytes44,
+    /// current and latched registers as u32 and a 64-bit timestamp (BGB, VBA-M, SameBoy, mGBA)
+    Bytes48,
+}
+
+impl RtcFooter {
+    /// Accepted footer sizes, for `SaveRam::load`
+    pub const SIZES: [usize; 3] = [48, 44, 32];
+
+    fn from_size(size: usize) -> Option<Self> {
+        match size {
+            32 => Some(RtcFooter::Bytes32),
+            44 => Some(RtcFooter::Bytes44),
+            48 => Some(RtcFooter::Bytes48),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for RtcFooter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .ok()
+            .and_then(RtcFooter::from_size)
+            .ok_or_else(|| format!("unknown RTC footer size {s}, expected 32, 44 or 48"))
+    }
+}
+
+/// MBC3 RTC registers (S, M, H, DL, DH) and the host time they were saved at
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RtcState {
+    pub regs: [u8; 5],
+    pub latched: [u8; 5],
+    /// UNIX timestamp, in seconds, 0 when unknown (saved with a cycle clock)
+    pub timestamp: u64,
+}
+
+impl RtcState {
+    /// `footer` must be 32, 44 or 48 bytes long
+    pub fn decode(footer: &[u8]) -> Option<Self> {
+        let reg = |i: usize| footer[i * 4];
+        let regs = [reg(0), reg(1), reg(2), reg(3), reg(4)];
+
+        let (latched, timestamp) = match RtcFooter::from_size(footer.len())? {
+            RtcFooter::Bytes32 => (regs, u64::from_le_bytes(footer[24..32].try_into().unwrap())),
+            RtcFooter::Bytes44 => (
+                [reg(5), reg(6), reg(7), reg(8), reg(9)],
+                u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+            ),
+            RtcFooter::Bytes48 => (
+                [reg(5), reg(6), reg(7), reg(8), reg(9)],
+                u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            ),
+        };
+
+        Some(RtcState {
+            regs,
+            latched,
+            timestamp,
+        })
+    }
+
+    pub fn encode(&self, layout: RtcFooter) -> Vec<u8> {
+        let mut footer = vec![];
+        let regs = match layout {
+            RtcFooter::Bytes32 => self.regs.to_vec(),
+            _ => [self.regs, self.latched].concat(),
+        };
+        for reg in regs {
+            footer.extend_from_slice(&(reg as u32).to_le_bytes());
+        }
+
+        match layout {
+            RtcFooter::Bytes32 => {
+                footer.extend_from_slice(&[0; 4]);
+                footer.extend_from_slice(&self.timestamp.to_le_bytes());
+            }
+            // truncated, as the emulators that use it do
+            RtcFooter::Bytes44 => footer.extend_from_slice(&(self.timestamp as u32).to_le_bytes()),
+            RtcFooter::Bytes48 => footer.extend_from_slice(&self.timestamp.to_le_bytes()),
+        }
+        footer
+    }
+}
+
+/// Save file flavours, told apart by their extension
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SaveFormat {
+    /// `.save`, written by this emulator: RAM followed by the 48-byte RTC footer
+    Native,
+    /// `.sav` of BGB, SameBoy, mGBA, VBA-M and flash carts: RAM followed by a RTC footer
+    Sav,
+    /// `.srm` of RetroArch: the RAM alone, the RTC is not part of it
+    Srm,
+}
+
+impl SaveFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "save" => Some(SaveFormat::Native),
+            "sav" => Some(SaveFormat::Sav),
+            "srm" => Some(SaveFormat::Srm),
+            _ => None,
+        }
+    }
+}
+
+/// Content of a save file, independent of the format it came from
+#[derive(PartialEq, Debug)]
+pub struct SaveData {
+    pub ram: Vec<u8>,
+    pub rtc: Option<RtcState>,
+}
+
+impl SaveData {
+    /// Split a save file into RAM and RTC. Without the cartridge header, the
+    /// RAM size is the largest known size that leaves a valid footer behind
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let ram_size = RAM_SIZES.iter().rev().find(|&&size| {
+            data.len() >= size
+                && (data.len() == size || RtcFooter::from_size(data.len() - size).is_some())
+        })?;
+
+        let (ram, footer) = data.split_at(*ram_size);
+        Some(SaveData {
+            ram: ram.to_vec(),
+            rtc: RtcState::decode(footer),
+        })
+    }
+
+    /// `footer` is the RTC layout for `.sav` files, it defaults to 48 bytes
+    pub fn encode(&self, format: SaveFormat, footer: Option<RtcFooter>) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        let layout = match format {
+            SaveFormat::Native => Some(RtcFooter::Bytes48),
+            SaveFormat::Sav => Some(footer.unwrap_or(RtcFooter::Bytes48)),
+            SaveFormat::Srm => None,
+        };
+
+        if let (Some(rtc), Some(layout)) = (&self.rtc, layout) {
+            data.extend_from_slice(&rtc.encode(layout));
+        }
+        data
+    }
+}
+
+/// Convert the save file at `input` to the format given by the extension of `output`
+pub fn convert(input: &Path, output: &Path, footer: Option<RtcFooter>) -> io::Result<()> {
+    let format = SaveFormat::from_path(output).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            "output must be a .save, .sav or .srm file",
+        )
+    })?;
+
+    let data = fs::read(input)?;
+    let save = SaveData::parse(&data).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} bytes is not a known save file size", data.len()),
+        )
+    })?;
+
+    fs::write(output, save.encode(format, footer))
+}
+
+#[test]
+fn test_rtc_footer_roundtrip() {
+    let rtc = RtcState {
+        regs: [12, 34, 5, 0xAB, 0b0100_0001],
+        latched: [1, 2, 3, 4, 0],
+        timestamp: 1_700_000_000,
+    };
+
+    let footer = rtc.encode(RtcFooter::Bytes48);
+    assert_eq!(footer.len(), 48);
+    assert_eq!(&footer[16..20], &[0b0100_0001, 0, 0, 0]);
+    assert_eq!(RtcState::decode(&footer), Some(rtc));
+
+    let footer = rtc.encode(RtcFooter::Bytes44);
+    assert_eq!(RtcState::decode(&footer), Some(rtc));
+
+    let footer = rtc.encode(RtcFooter::Bytes32);
+    assert_eq!(footer.len(), 32);
+    let decoded = RtcState::decode(&footer).unwrap();
+    assert_eq!((decoded.regs, decoded.latched), (rtc.regs, rtc.regs));
+}
+
+#[test]
+fn test_save_conversion() {
+    let mut data = vec![0x42; 0x8000];
+    let rtc = RtcState {
+        regs: [1, 2, 3, 4, 0],
+        latched: [1, 2, 3, 4, 0],
+        timestamp: 42,
+    };
+    data.extend_from_slice(&rtc.encode(RtcFooter::Bytes44));
+
+    let save = SaveData::parse(&data).unwrap();
+    assert_eq!(save.ram.len(), 0x8000);
+    assert_eq!(save.rtc, Some(rtc));
+
+    assert_eq!(save.encode(SaveFormat::Native, None).len(), 0x8000 + 48);
+    assert_eq!(save.encode(SaveFormat::Srm, None).len(), 0x8000);
+    assert_eq!(
+        save.encode(SaveFormat::Sav, Some(RtcFooter::Bytes32)).len(),
+        0x8000 + 32
+    );
+
+    assert_eq!(SaveData::parse(&[0; 0x2001]), None);
+}
@@ -0,0 +1,29 @@
+use crate::{
+    cartridge::clock::{Clock, WallClock},
+    model::Model,
+    serial::{Disconnected, SerialDevice},
+};
+
+/// How to power on the system, besides the ROM itself
+pub struct Config {
+    /// time base of the cartridge RTC
+    pub clock: Box<dyn Clock>,
+    /// DMG (256 bytes) or CGB (2304 bytes) boot ROM to run first.
+    /// Without it, the system starts in the state the boot ROM leaves it in
+    pub boot_rom: Option<Vec<u8>>,
+    /// console to emulate, picked from the cartridge header when `None`
+    pub model: Option<Model>,
+    /// plugged into the link port
+    pub serial: Box<dyn SerialDevice>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            clock: Box::new(WallClock),
+            boot_rom: None,
+            model: None,
+            serial: Box::new(Disconnected),
+        }
+    }
+}
@@ -1,6 +1,14 @@
-use std::path::PathBuf;
-
-use crate::{mmu::MMU, opcodes::CPU_OPCODES};
+use std::{io, path::PathBuf};
+
+use crate::{
+    config::Config,
+    error::EmulatorError,
+    interrupt::InterruptController,
+    joypad::Button,
+    mmu::{GbMode, MMU},
+    opcodes::CPU_OPCODES,
+    ppu::CYCLES_PER_FRAME,
+};
 use bitflags::bitflags;
 
 bitflags! {
@@ -42,7 +50,25 @@ pub trait Mem {
     }
 }
 
-pub struct CPU {
+/// The rest of the system as seen by the CPU: the `MMU` in the emulator,
+/// a flat 64 KiB RAM in the instruction tests
+pub trait Bus: Mem {
+    /// Advance every other component by `time` T-cycles
+    fn execute_cycle(&mut self, time: u32);
+    /// IF and IE
+    fn interrupts(&mut self) -> &mut InterruptController;
+    /// A joypad press wakes the CPU up from STOP
+    fn joypad_pressed(&self) -> bool;
+    /// T-cycles the CPU has to wait for a DMA
+    fn take_dma_stall(&mut self) -> u32;
+    /// Called by STOP, switch the CGB speed if it was requested
+    fn try_switch_speed(&mut self) -> bool;
+    fn reset_divider(&mut self);
+    /// Stop the emulator at the end of the current instruction
+    fn raise(&self, error: EmulatorError);
+}
+
+pub struct CPU<M: Bus = MMU> {
     pub program_counter: u16,
     pub stack_pointer: u16,
     pub status: StatusFlags,
@@ -53,10 +79,18 @@ pub struct CPU {
     pub e: u8,
     pub h: u8,
     pub l: u8,
-    pub mmu: MMU,
+    /// Interrupt Master Enable
+    pub ime: bool,
+    /// EI only sets IME after the following instruction
+    ime_scheduled: bool,
+    pub halted: bool,
+    /// HALT with IME=0 and an interrupt pending: the next opcode byte is read twice
+    halt_bug: bool,
+    pub stopped: bool,
+    pub mmu: M,
 }
 
-impl Mem for CPU {
+impl<M: Bus> Mem for CPU<M> {
     fn mem_read_u8(&self, addr: u16) -> u8 {
         self.mmu.mem_read_u8(addr)
     }
@@ -67,23 +101,93 @@ impl Mem for CPU {
 }
 
 impl CPU {
-    pub fn new(path: PathBuf) -> Self {
-        CPU {
-            a: 0,
-            b: 0,
-            c: 0,
-            d: 0,
-            e: 0,
-            h: 0,
-            l: 0,
-            status: StatusFlags::from_bits_truncate(0x00),
-            program_counter: 0,
-            stack_pointer: 0,
-            mmu: MMU::new(path),
+    pub fn with_config(path: PathBuf, config: Config) -> Result<Self, EmulatorError> {
+        let mut cpu = CPU::with_bus(MMU::new(path, config)?);
+
+        if !cpu.mmu.booting() {
+            cpu.skip_boot();
+        }
+        Ok(cpu)
+    }
+
+    /// Set the registers as the boot ROM leaves them, and jump to the cartridge
+    /// https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
+    fn skip_boot(&mut self) {
+        let color_mode = self.mmu.mode == GbMode::Color;
+        let header_checksum = self.mmu.mem_read_u8(0x014D);
+        let [af, bc, de, hl] = self
+            .mmu
+            .model
+            .post_boot_registers(color_mode, header_checksum);
+        self.set_af(af);
+        self.set_bc(bc);
+        self.set_de(de);
+        self.set_hl(hl);
+        self.stack_pointer = 0xFFFE;
+        self.program_counter = 0x0100;
+    }
+
+    /// Run until the PPU completes a frame, or a frame worth of cycles
+    /// went by (LCD off, STOP mode). Stops early on an error
+    pub fn run_frame(&mut self) -> Result<(), EmulatorError> {
+        let budget = CYCLES_PER_FRAME << self.mmu.double_speed as u32;
+        let mut elapsed = 0;
+
+        self.mmu.ppu.frame_ready = false;
+        while !self.mmu.ppu.frame_ready && elapsed < budget {
+            elapsed += self.step();
+            if let Some(error) = self.mmu.take_fault() {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a frame like `run_frame`, with `peer` (a second Game Boy on the
+    /// other end of a `LinkPort` cable) kept within one instruction of this one
+    pub fn run_frame_linked(&mut self, peer: &mut CPU) -> Result<(), EmulatorError> {
+        let budget = CYCLES_PER_FRAME << self.mmu.double_speed as u32;
+        let mut elapsed = 0;
+        let mut peer_elapsed = 0;
+
+        self.mmu.ppu.frame_ready = false;
+        while !self.mmu.ppu.frame_ready && elapsed < budget {
+            elapsed += self.step();
+            if let Some(error) = self.mmu.take_fault() {
+                return Err(error);
+            }
+            while peer_elapsed < elapsed {
+                peer_elapsed += peer.step();
+                if let Some(error) = peer.mmu.take_fault() {
+                    return Err(error);
+                }
+            }
         }
+        Ok(())
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.mmu.joypad.press(button);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.mmu.joypad.release(button);
+    }
+
+    /// Write the battery backed cartridge RAM to disk
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        self.mmu.flush_save()
+    }
+
+    /// Whether the cartridge rumble motor is currently on
+    pub fn rumble(&self) -> bool {
+        self.mmu.mbc.rumble()
     }
+}
 
-    pub fn new_test() -> Self {
+impl<M: Bus> CPU<M> {
+    /// Power on with every register cleared
+    pub fn with_bus(mmu: M) -> Self {
         CPU {
             a: 0,
             b: 0,
@@ -95,37 +199,106 @@ impl CPU {
             status: StatusFlags::from_bits_truncate(0x00),
             program_counter: 0,
             stack_pointer: 0,
-            mmu: MMU::new("lmao".into()),
+            ime: false,
+            ime_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            mmu,
         }
     }
 
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
-        for i in 0..(program.len() as u16) {
-            // TODO: implement MBC
-            self.mem_write_u8(i, program[i as usize]);
+    /// Execute one instruction (or service an interrupt, or idle in HALT),
+    /// advance the rest of the system by the same amount of T-cycles
+    /// and return it.
+    ///
+    /// The instruction runs as a whole before the timer, PPU and DMAs are
+    /// clocked: they only see the bus at instruction boundaries, as if every
+    /// access happened on the first M-cycle. Effects in the middle of an
+    /// instruction are lost, like a write landing in the TIMA reload cycle or
+    /// STAT changing between the read and the write of a read-modify-write.
+    /// The test ROMs relying on them are in `test_roms/known_failures.txt`
+    pub fn step(&mut self) -> u32 {
+        if self.stopped {
+            // only a joypad press leaves STOP mode, nothing is clocked meanwhile
+            if !self.mmu.joypad_pressed() {
+                return 4;
+            }
+            self.stopped = false;
         }
 
-        self.run();
-    }
+        if self.halted {
+            if self.mmu.interrupts().pending().is_empty() {
+                self.mmu.execute_cycle(4);
+                return 4;
+            }
+            self.halted = false;
+        }
 
-    fn run(&mut self) {
-        let ref all_opcodes = *CPU_OPCODES;
+        let interrupt_time = self.handle_interrupt();
+        if interrupt_time > 0 {
+            self.mmu.execute_cycle(interrupt_time);
+            return interrupt_time;
+        }
 
-        loop {
-            let code = self.fetch_opcode();
+        let enable_ime = self.ime_scheduled;
+        let code = self.fetch_opcode();
+
+        // the hardware locks up on the unused opcodes (0xD3, 0xDB, ...)
+        let opcode = match CPU_OPCODES.get(&code) {
+            Some(opcode) => opcode,
+            None => {
+                self.mmu.raise(EmulatorError::InvalidOpcode {
+                    opcode: code,
+                    addr: self.program_counter,
+                });
+                return 4;
+            }
+        };
 
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
             self.program_counter += 1;
-            let pc_state = self.program_counter;
+        }
+        let pc_state = self.program_counter;
+
+        let time = self.decode(opcode) as u32;
+        self.mmu.execute_cycle(time);
+
+        if self.program_counter == pc_state {
+            self.program_counter += opcode.bytes as u16 - 1;
+        }
 
-            let opcode = all_opcodes
-                .get(&code)
-                .expect(&format!("Opcode {:x} is not recognized", code));
+        // DI in the meantime cancels a pending EI
+        if enable_ime && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
+
+        // the CPU is paused while the CGB DMA copies to VRAM
+        let stall = self.mmu.take_dma_stall();
+        self.mmu.execute_cycle(stall);
+
+        time + stall
+    }
 
-            let time = self.decode(opcode);
+    /// Service the highest priority pending interrupt, if IME allows it,
+    /// and return the T-cycles spent doing so
+    /// https://gbdev.io/pandocs/Interrupts.html#interrupt-handling
+    fn handle_interrupt(&mut self) -> u32 {
+        if !self.ime {
+            return 0;
+        }
 
-            if self.program_counter == pc_state {
-                self.program_counter += opcode.bytes as u16 - 1;
+        match self.mmu.interrupts().acknowledge() {
+            Some(interrupt) => {
+                self.ime = false;
+                self.stack_push(self.program_counter);
+                self.program_counter = interrupt.vector();
+                20
             }
+            None => 0,
         }
     }
 
@@ -135,7 +308,8 @@ impl CPU {
         if op != 0xCB {
             op as u16
         } else {
-            0xCB_u16 << 8 | op as u16
+            // prefixed opcodes are keyed 0xCBxx
+            0xCB_u16 << 8 | self.mem_read_u8(self.program_counter.wrapping_add(1)) as u16
         }
     }
 
@@ -145,27 +319,27 @@ impl CPU {
     }
 
     pub fn get_b(&self) -> u8 {
-        self.a
+        self.b
     }
 
     pub fn get_c(&self) -> u8 {
-        self.a
+        self.c
     }
 
     pub fn get_d(&self) -> u8 {
-        self.a
+        self.d
     }
 
     pub fn get_e(&self) -> u8 {
-        self.a
+        self.e
     }
 
     pub fn get_h(&self) -> u8 {
-        self.a
+        self.h
     }
 
     pub fn get_l(&self) -> u8 {
-        self.a
+        self.l
     }
 
     pub fn get_af(&self) -> u16 {
@@ -270,47 +444,90 @@ impl CPU {
 
     //* Other CPU functions *//
 
+    /// EI: IME is set after the next instruction
     pub fn enable_interrupt(&mut self) {
-        todo!();
+        self.ime_scheduled = true;
     }
 
+    /// DI: takes effect immediately
     pub fn disable_interrupt(&mut self) {
-        todo!();
+        self.ime = false;
+        self.ime_scheduled = false;
     }
 
+    /// Relative jump, by the signed operand from the end of the instruction
     pub fn cpu_jr(&mut self) {
-        todo!();
+        let offset = self.mem_read_u8(self.program_counter) as i8;
+        self.program_counter = self
+            .program_counter
+            .wrapping_add(1)
+            .wrapping_add(offset as u16);
     }
 
+    /// https://gbdev.io/pandocs/halt.html
     pub fn halt(&mut self) {
-        todo!();
+        if !self.ime && !self.mmu.interrupts().pending().is_empty() {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
     }
 
+    /// https://gbdev.io/pandocs/Reducing_Power_Consumption.html#using-the-stop-instruction
     pub fn stop(&mut self) {
-        todo!();
+        if self.mmu.try_switch_speed() {
+            return;
+        }
+
+        self.mmu.reset_divider();
+        self.stopped = true;
     }
 
     //* Stack methods *//
+    /// The stack can live anywhere in memory (usually WRAM or HRAM), SP wraps around
     pub fn stack_push(&mut self, data: u16) {
-        self.stack_pointer -= 2;
-        self.validate_sp();
+        self.stack_pointer = self.stack_pointer.wrapping_sub(2);
         self.mem_write_u16(self.stack_pointer, data);
     }
 
     pub fn stack_pop(&mut self) -> u16 {
         let res = self.mem_read_u16(self.stack_pointer);
-
-        self.stack_pointer += 2;
-        self.validate_sp();
-
+        self.stack_pointer = self.stack_pointer.wrapping_add(2);
         res
     }
+}
 
-    fn validate_sp(&self) {
-        if self.get_sp() > 0xFFFE {
-            panic!("Stack underflow");
-        } else if self.get_sp() < 0xFF80 {
-            panic!("Stack overflow");
-        }
-    }
+#[test]
+fn test_run_frame_linked() {
+    use crate::serial::LinkPort;
+
+    // SB = `data`, SC = `control`, then JR -2
+    let rom = |name: &str, data: u8, control: u8| {
+        let path = std::env::temp_dir().join(name);
+        let mut rom = vec![0; 0x8000];
+        let program = [
+            0x3E, data, 0xE0, 0x01, 0x3E, control, 0xE0, 0x02, 0x18, 0xFE,
+        ];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(&program);
+        std::fs::write(&path, &rom).unwrap();
+        path
+    };
+    let master_rom = rom("gb_emulator_test_linked_master.gb", 0x99, 0x81);
+    let slave_rom = rom("gb_emulator_test_linked_slave.gb", 0x42, 0x80);
+
+    let (a, b) = LinkPort::pair();
+    let config = |port: LinkPort| Config {
+        serial: Box::new(port),
+        ..Config::default()
+    };
+    let mut master = CPU::with_config(master_rom.clone(), config(a)).unwrap();
+    let mut slave = CPU::with_config(slave_rom.clone(), config(b)).unwrap();
+    master.run_frame_linked(&mut slave).unwrap();
+
+    assert_eq!(master.mem_read_u8(0xFF01), 0x42);
+    assert_eq!(slave.mem_read_u8(0xFF01), 0x99);
+    assert_eq!(slave.mem_read_u8(0xFF02) & 0x80, 0);
+
+    std::fs::remove_file(master_rom).unwrap();
+    std::fs::remove_file(slave_rom).unwrap();
 }
@@ -0,0 +1,204 @@
+/// Memory buses, each one serves a single access per M-cycle
+#[derive(PartialEq)]
+enum Bus {
+    /// cartridge and WRAM
+    External,
+    Video,
+    Oam,
+    /// I/O registers, HRAM and IE
+    Internal,
+}
+
+fn bus(addr: u16) -> Bus {
+    match addr {
+        0x8000..=0x9FFF => Bus::Video,
+        0xFE00..=0xFEFF => Bus::Oam,
+        0xFF00..=0xFFFF => Bus::Internal,
+        _ => Bus::External,
+    }
+}
+
+/// https://gbdev.io/pandocs/OAM_DMA_Transfer.html
+///
+/// Copies 160 bytes from `XX00` to OAM, one per M-cycle, after writing `XX` to 0xFF46.
+/// Meanwhile the CPU can't use OAM nor the bus the source is on: reads return
+/// the byte being transferred, writes are lost. HRAM is always reachable.
+pub struct OamDma {
+    /// last value written to 0xFF46
+    pub register: u8,
+    source: u16,
+    /// M-cycles before the first byte is copied
+    delay: u8,
+    /// next byte to copy, 160 when the transfer is over
+    index: u16,
+    /// byte moved during the last M-cycle, which the CPU sees on a bus conflict
+    pub last_byte: u8,
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        OamDma {
+            register: 0xFF,
+            source: 0,
+            delay: 0,
+            index: 160,
+            last_byte: 0xFF,
+        }
+    }
+
+    pub fn start(&mut self, data: u8) {
+        self.register = data;
+        self.source = (data as u16) << 8;
+        self.delay = 1;
+        self.index = 0;
+    }
+
+    pub fn active(&self) -> bool {
+        self.delay == 0 && self.index < 160
+    }
+
+    /// Advance by 1 M-cycle and return the (source, destination) of the byte to copy
+    pub fn execute_m_cycle(&mut self) -> Option<(u16, u16)> {
+        if self.delay > 0 {
+            self.delay -= 1;
+            return None;
+        }
+        if self.index >= 160 {
+            return None;
+        }
+
+        let source = self.source_addr();
+        let destination = 0xFE00 + self.index;
+        self.index += 1;
+        Some((source, destination))
+    }
+
+    fn source_addr(&self) -> u16 {
+        let source = self.source + self.index;
+        // sources above WRAM read from echo RAM
+        if source >= 0xE000 {
+            source - 0x2000
+        } else {
+            source
+        }
+    }
+
+    /// Value the CPU reads at `addr` if the transfer keeps it from reaching it
+    pub fn conflict(&self, addr: u16) -> Option<u8> {
+        if !self.active() {
+            return None;
+        }
+
+        match bus(addr) {
+            Bus::Oam => Some(0xFF),
+            Bus::Internal => None,
+            b if b == bus(self.source_addr()) => Some(self.last_byte),
+            _ => None,
+        }
+    }
+}
+
+/// https://gbdev.io/pandocs/CGB_Registers.html#lcd-vram-dma-transfers
+///
+/// CGB copies to VRAM in blocks of 16 bytes, either all at once (general purpose DMA)
+/// or one block at the start of each HBlank (HBlank DMA). The CPU is paused during a copy.
+pub struct Hdma {
+    source: u16,
+    destination: u16,
+    /// blocks left minus one, as read back from 0xFF55
+    remaining: u8,
+    /// a HBlank DMA is in progress
+    hblank: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma {
+            source: 0,
+            destination: 0x8000,
+            remaining: 0xFF,
+            hblank: false,
+        }
+    }
+
+    pub fn hblank_active(&self) -> bool {
+        self.hblank
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // bit 7 is cleared while a HBlank DMA is running, 0xFF once it's over
+            0xFF55 => ((!self.hblank as u8) << 7) | (self.remaining & 0x7F),
+            // source and destination are write only
+            _ => 0xFF,
+        }
+    }
+
+    /// Return the number of blocks to copy right away, for a general purpose DMA
+    pub fn write(&mut self, addr: u16, data: u8) -> Option<u8> {
+        match addr {
+            0xFF51 => self.source = (data as u16) << 8 | (self.source & 0x00F0),
+            0xFF52 => self.source = (self.source & 0xFF00) | (data & 0xF0) as u16,
+            0xFF53 => {
+                self.destination =
+                    0x8000 | ((data & 0x1F) as u16) << 8 | (self.destination & 0x00F0)
+            }
+            0xFF54 => self.destination = (self.destination & 0xFF00) | (data & 0xF0) as u16,
+            0xFF55 => {
+                // clearing bit 7 during a HBlank DMA stops it
+                if self.hblank && data & 0x80 == 0 {
+                    self.hblank = false;
+                    return None;
+                }
+                self.remaining = data & 0x7F;
+                if data & 0x80 != 0 {
+                    self.hblank = true;
+                } else {
+                    return Some(self.remaining + 1);
+                }
+            }
+            _ => unreachable!(),
+        }
+        None
+    }
+
+    /// Return the (source, destination) of the next 16 bytes to copy and move past them
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, self.destination);
+        self.source = self.source.wrapping_add(16);
+        self.destination += 16;
+        self.remaining = self.remaining.wrapping_sub(1);
+
+        // the transfer stops early when the destination leaves VRAM
+        if self.destination > 0x9FF0 {
+            self.destination = 0x8000;
+            self.remaining = 0xFF;
+        }
+        if self.remaining == 0xFF {
+            self.hblank = false;
+        }
+        block
+    }
+}
+
+#[test]
+fn test_oam_dma_duration() {
+    let mut dma = OamDma::new();
+    dma.start(0xC1);
+    assert_eq!(dma.execute_m_cycle(), None);
+    assert!(dma.active());
+    assert_eq!(dma.execute_m_cycle(), Some((0xC100, 0xFE00)));
+
+    for _ in 1..160 {
+        assert!(dma.execute_m_cycle().is_some());
+    }
+    assert!(!dma.active());
+    assert_eq!(dma.execute_m_cycle(), None);
+
+    dma.start(0xFE);
+    dma.execute_m_cycle();
+    assert_eq!(dma.execute_m_cycle(), Some((0xDE00, 0xFE00)));
+    assert_eq!(dma.conflict(0xC000), Some(dma.last_byte));
+    assert_eq!(dma.conflict(0x8000), None);
+    assert_eq!(dma.conflict(0xFF80), None);
+}
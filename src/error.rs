@@ -0,0 +1,93 @@
+use std::{error::Error, fmt, io, str::FromStr};
+
+#[derive(Debug)]
+pub enum EmulatorError {
+    Io(io::Error),
+    /// cartridge type byte (0x0147) of a mapper we don't emulate
+    UnsupportedMapper(u8),
+    /// size of a ROM too small to hold the cartridge header
+    TruncatedRom(usize),
+    BadSave(String),
+    /// size of a boot ROM that doesn't match the emulated model
+    InvalidBootRom(usize),
+    InvalidOpcode {
+        opcode: u16,
+        addr: u16,
+    },
+    /// `data` is `None` for a read
+    InvalidAccess {
+        addr: u16,
+        data: Option<u8>,
+    },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::Io(e) => write!(f, "{e}"),
+            EmulatorError::UnsupportedMapper(byte) => {
+                write!(f, "cartridge type {byte:02x} is not supported")
+            }
+            EmulatorError::TruncatedRom(len) => {
+                write!(
+                    f,
+                    "ROM is too small ({len} bytes) to hold a cartridge header"
+                )
+            }
+            EmulatorError::BadSave(reason) => write!(f, "invalid save file: {reason}"),
+            EmulatorError::InvalidBootRom(len) => {
+                write!(f, "invalid boot ROM size {len} for the emulated model")
+            }
+            EmulatorError::InvalidOpcode { opcode, addr } => {
+                write!(f, "invalid opcode {opcode:02x} at {addr:04x}")
+            }
+            EmulatorError::InvalidAccess { addr, data: None } => {
+                write!(f, "invalid read at {addr:04x}")
+            }
+            EmulatorError::InvalidAccess {
+                addr,
+                data: Some(data),
+            } => write!(f, "invalid write of {data:02x} at {addr:04x}"),
+        }
+    }
+}
+
+impl Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EmulatorError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EmulatorError {
+    fn from(e: io::Error) -> Self {
+        EmulatorError::Io(e)
+    }
+}
+
+/// What to do when the program touches unmapped memory or registers of
+/// hardware that isn't emulated
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AccessPolicy {
+    /// print the first access to each address and carry on, reads return 0xFF
+    Log,
+    /// carry on silently, reads return 0xFF
+    Ignore,
+    /// stop the emulator with an `InvalidAccess` error
+    Halt,
+}
+
+impl FromStr for AccessPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(AccessPolicy::Log),
+            "ignore" => Ok(AccessPolicy::Ignore),
+            "halt" => Ok(AccessPolicy::Halt),
+            _ => Err(format!("unknown policy {s}, expected log, ignore or halt")),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::cpu::Mem;
+use bitflags::bitflags;
+
+bitflags! {
+    /// https://gbdev.io/pandocs/Interrupt_Sources.html
+    ///
+    /// 7     bit     0
+    /// ----       ----
+    /// 0 0 0 J S T L V
+    /// | | | | | | | |
+    /// | | | | | | | +- VBlank   (0x40)
+    /// | | | | | | +--- LCD STAT (0x48)
+    /// | | | | | +----- Timer    (0x50)
+    /// | | | | +------- Serial   (0x58)
+    /// | | | +--------- Joypad   (0x60)
+    /// +-+-+----------- Unused
+    #[derive(Clone, Copy, PartialEq)]
+    pub struct Interrupt: u8 {
+        const VBLANK = 0b0000_0001;
+        const LCD_STAT = 0b0000_0010;
+        const TIMER = 0b0000_0100;
+        const SERIAL = 0b0000_1000;
+        const JOYPAD = 0b0001_0000;
+    }
+}
+
+impl Interrupt {
+    /// Address the CPU jumps to when servicing this (single) interrupt
+    pub fn vector(self) -> u16 {
+        0x0040 + 8 * self.bits().trailing_zeros() as u16
+    }
+}
+
+/// IF (0xFF0F) and IE (0xFFFF)
+/// https://gbdev.io/pandocs/Interrupts.html
+pub struct InterruptController {
+    pub flag: Interrupt,
+    pub enable: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        InterruptController {
+            flag: Interrupt::empty(),
+            enable: 0,
+        }
+    }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.flag |= interrupt;
+    }
+
+    /// Interrupts both requested and enabled, regardless of IME
+    pub fn pending(&self) -> Interrupt {
+        self.flag & Interrupt::from_bits_truncate(self.enable)
+    }
+
+    /// Clear and return the highest priority pending interrupt
+    pub fn acknowledge(&mut self) -> Option<Interrupt> {
+        let pending = self.pending().bits();
+        if pending == 0 {
+            return None;
+        }
+
+        let interrupt = Interrupt::from_bits_truncate(pending & pending.wrapping_neg());
+        self.flag.remove(interrupt);
+        Some(interrupt)
+    }
+}
+
+impl Mem for InterruptController {
+    fn mem_read_u8(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF0F => 0b1110_0000 | self.flag.bits(),
+            0xFFFF => self.enable,
+            _ => panic!("Interrupt controller can't read {addr:4x}"),
+        }
+    }
+
+    fn mem_write_u8(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xFF0F => self.flag = Interrupt::from_bits_truncate(data),
+            0xFFFF => self.enable = data,
+            _ => panic!("Interrupt controller can't write {addr:4x}"),
+        }
+    }
+}
+
+#[test]
+fn test_acknowledge_priority() {
+    let mut ic = InterruptController::new();
+    ic.request(Interrupt::TIMER | Interrupt::LCD_STAT | Interrupt::JOYPAD);
+    ic.enable = (Interrupt::TIMER | Interrupt::JOYPAD).bits();
+
+    let first = ic.acknowledge().unwrap();
+    assert_eq!(first.vector(), 0x0050);
+    assert_eq!(ic.acknowledge().unwrap().vector(), 0x0060);
+    assert!(ic.acknowledge().is_none());
+    assert_eq!(ic.mem_read_u8(0xFF0F), 0b1110_0010);
+}
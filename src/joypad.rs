@@ -0,0 +1,144 @@
+use crate::{cpu::Mem, interrupt::Interrupt};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// (is an action button, bit in the lower nibble of P1)
+    fn line(self) -> (bool, u8) {
+        match self {
+            Button::Right => (false, 0b0001),
+            Button::Left => (false, 0b0010),
+            Button::Up => (false, 0b0100),
+            Button::Down => (false, 0b1000),
+            Button::A => (true, 0b0001),
+            Button::B => (true, 0b0010),
+            Button::Select => (true, 0b0100),
+            Button::Start => (true, 0b1000),
+        }
+    }
+}
+
+/// P1/JOYP (0xFF00)
+/// https://gbdev.io/pandocs/Joypad_Input.html
+///
+/// 7     bit     0
+/// ----       ----
+/// 1 1 A D 3 2 1 0
+/// | | | | | | | |
+/// | | | | | | | +- Right or A     (0=Pressed)
+/// | | | | | | +--- Left or B      (0=Pressed)
+/// | | | | | +----- Up or Select   (0=Pressed)
+/// | | | | +------- Down or Start  (0=Pressed)
+/// | | | +--------- P14 select directions (0=Select)
+/// | | +----------- P15 select action buttons (0=Select)
+/// +-+------------- Unused
+pub struct Joypad {
+    select: u8,
+    /// pressed directions, 1 = pressed
+    directions: u8,
+    /// pressed action buttons, 1 = pressed
+    actions: u8,
+    pub interrupt: Interrupt,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            select: 0b0011_0000,
+            directions: 0,
+            actions: 0,
+            interrupt: Interrupt::empty(),
+        }
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.update(|joypad| match button.line() {
+            (true, bit) => joypad.actions |= bit,
+            (false, bit) => joypad.directions |= bit,
+        });
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.update(|joypad| match button.line() {
+            (true, bit) => joypad.actions &= !bit,
+            (false, bit) => joypad.directions &= !bit,
+        });
+    }
+
+    /// Lower nibble of P1 as seen through the select lines, 0 = pressed
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & 0b0001_0000 == 0 {
+            pressed |= self.directions;
+        }
+        if self.select & 0b0010_0000 == 0 {
+            pressed |= self.actions;
+        }
+        !pressed & 0x0F
+    }
+
+    /// Apply a change and request the interrupt if a line went from high to low
+    fn update<F: FnOnce(&mut Self)>(&mut self, change: F) {
+        let old = self.lines();
+        change(self);
+        if old & !self.lines() != 0 {
+            self.interrupt |= Interrupt::JOYPAD;
+        }
+    }
+}
+
+impl Mem for Joypad {
+    fn mem_read_u8(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF00 => 0b1100_0000 | self.select | self.lines(),
+            _ => panic!("Joypad can't read {addr:4x}"),
+        }
+    }
+
+    fn mem_write_u8(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xFF00 => self.update(|joypad| joypad.select = data & 0b0011_0000),
+            _ => panic!("Joypad can't write {addr:4x}"),
+        }
+    }
+}
+
+#[test]
+fn test_select_lines() {
+    let mut joypad = Joypad::new();
+    joypad.press(Button::A);
+    joypad.press(Button::Down);
+
+    joypad.mem_write_u8(0xFF00, 0b0010_0000);
+    assert_eq!(joypad.mem_read_u8(0xFF00), 0b1110_0111);
+
+    joypad.mem_write_u8(0xFF00, 0b0001_0000);
+    assert_eq!(joypad.mem_read_u8(0xFF00), 0b1101_1110);
+
+    joypad.mem_write_u8(0xFF00, 0b0011_0000);
+    assert_eq!(joypad.mem_read_u8(0xFF00), 0b1111_1111);
+}
+
+#[test]
+fn test_interrupt_on_press() {
+    let mut joypad = Joypad::new();
+    joypad.press(Button::Start);
+    assert!(joypad.interrupt.is_empty());
+
+    joypad.mem_write_u8(0xFF00, 0b0001_0000);
+    assert!(joypad.interrupt.contains(Interrupt::JOYPAD));
+
+    joypad.interrupt = Interrupt::empty();
+    joypad.release(Button::Start);
+    assert!(joypad.interrupt.is_empty());
+}
@@ -1,11 +1,355 @@
 mod alu;
+mod apu;
+mod audio;
 mod cartridge;
+mod config;
 mod cpu;
+mod dma;
+mod error;
+mod interrupt;
+mod joypad;
 mod mmu;
+mod model;
 mod opcodes;
+mod ppu;
+mod serial;
+#[cfg(test)]
+mod sm83;
+mod test_rom;
 mod timer;
 mod utils;
 
+use std::{
+    fs,
+    path::PathBuf,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use audio::AudioOutput;
+use cartridge::{clock::CycleClock, header::CartridgeHeader, save_format::RtcFooter};
+use config::Config;
+use cpu::CPU;
+use error::{AccessPolicy, EmulatorError};
+use joypad::Button;
+use minifb::{Key, Scale, Window, WindowOptions};
+use model::Model;
+use ppu::{rgb555_to_rgb888, DMG_COLORS, SCREEN_HEIGHT, SCREEN_WIDTH};
+use serial::{
+    printer::{ImageFormat, Printer},
+    tcp::TcpLink,
+    LinkPort,
+};
+use structopt::StructOpt;
+use test_rom::{find_test_roms, run_test_rom, TestOutcome, DEFAULT_TIMEOUT};
+
+/// ~59.7 frames per second
+const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+
+const KEY_MAP: [(Key, Button); 8] = [
+    (Key::Right, Button::Right),
+    (Key::Left, Button::Left),
+    (Key::Up, Button::Up),
+    (Key::Down, Button::Down),
+    (Key::Z, Button::A),
+    (Key::X, Button::B),
+    (Key::Backspace, Button::Select),
+    (Key::Enter, Button::Start),
+];
+
+#[derive(StructOpt)]
+#[structopt(name = "gb_emulator")]
+struct Opt {
+    /// Path to the ROM to run
+    #[structopt(parse(from_os_str))]
+    rom: Option<PathBuf>,
+
+    /// Drive the cartridge RTC from emulated cycles instead of the host clock, for
+    /// reproducible runs. Without a saved clock, the RTC starts at this many seconds
+    /// (86400 is day 1, 00:00:00). A saved clock resumes where it was
+    #[structopt(long = "rtc-start")]
+    rtc_start: Option<u64>,
+
+    /// Boot ROM to run before the cartridge, the boot sequence is skipped without it
+    #[structopt(long = "boot-rom", parse(from_os_str))]
+    boot_rom: Option<PathBuf>,
+
+    /// Console to emulate: dmg0, dmg, mgb, sgb, sgb2, cgb or agb.
+    /// Picked from the cartridge header by default
+    #[structopt(long = "model")]
+    model: Option<Model>,
+
+    /// Write the save file every N seconds of emulated time, 0 to only save on exit
+    #[structopt(long = "autosave", default_value = "30")]
+    autosave: u64,
+
+    /// What to do on an access to unmapped memory: log, ignore or halt
+    #[structopt(long = "invalid-access", default_value = "log")]
+    invalid_access: AccessPolicy,
+
+    /// Wait for another gb_emulator to plug a link cable at this address,
+    /// e.g. 127.0.0.1:8765
+    #[structopt(long = "link-listen", conflicts_with = "link_connect")]
+    link_listen: Option<String>,
+
+    /// Plug a link cable into the gb_emulator listening at this address
+    #[structopt(long = "link-connect")]
+    link_connect: Option<String>,
+
+    /// Keep both ends of the link cable in lockstep, for reproducible transfers
+    #[structopt(long = "lockstep")]
+    lockstep: bool,
+
+    /// Plug a Game Boy Printer into the link port, printouts are written to this directory
+    #[structopt(
+        long = "printer",
+        parse(from_os_str),
+        raw(conflicts_with_all = r#"&["link_listen", "link_connect"]"#)
+    )]
+    printer: Option<PathBuf>,
+
+    /// Run this ROM on a second, headless Game Boy linked to the first one by a cable,
+    /// in the same process
+    #[structopt(
+        long = "link-rom",
+        parse(from_os_str),
+        raw(conflicts_with_all = r#"&["link_listen", "link_connect", "printer"]"#)
+    )]
+    link_rom: Option<PathBuf>,
+
+    /// Image format of the printouts: png, in the DMG screen colors, or pgm, in grayscale
+    #[structopt(long = "printer-format", default_value = "png")]
+    printer_format: ImageFormat,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Convert a save file between .save (this emulator), .sav (BGB, SameBoy,
+    /// mGBA, VBA-M, flash carts) and .srm (RetroArch), based on the extensions
+    #[structopt(name = "convert")]
+    Convert {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+        /// Size of the MBC3 RTC footer written to a .sav file: 32, 44 or 48
+        #[structopt(long = "rtc-footer")]
+        rtc_footer: Option<RtcFooter>,
+    },
+
+    /// Print the cartridge header of a ROM
+    #[structopt(name = "info")]
+    Info {
+        #[structopt(parse(from_os_str))]
+        rom: PathBuf,
+    },
+
+    /// Run Blargg or mooneye test ROMs headless and report their results.
+    /// Directories are searched for .gb and .gbc files
+    #[structopt(name = "test")]
+    Test {
+        #[structopt(parse(from_os_str), required = true)]
+        roms: Vec<PathBuf>,
+        /// Emulated seconds before giving up on a ROM, 60 by default
+        #[structopt(long = "timeout")]
+        timeout: Option<u64>,
+    },
+}
+
 fn main() {
-    println!("Hello, world!");
+    let opt = Opt::from_args();
+
+    match &opt.command {
+        Some(Command::Convert {
+            input,
+            output,
+            rtc_footer,
+        }) => {
+            if let Err(e) = cartridge::save_format::convert(input, output, *rtc_footer) {
+                eprintln!("Could not convert {}: {e}", input.display());
+                process::exit(1);
+            }
+        }
+        Some(Command::Info { rom }) => {
+            let header = fs::read(rom)
+                .map_err(EmulatorError::from)
+                .and_then(|data| CartridgeHeader::parse(&data));
+            match header {
+                Ok(header) => println!("{header}"),
+                Err(e) => {
+                    eprintln!("Could not read the header of {}: {e}", rom.display());
+                    process::exit(1);
+                }
+            }
+        }
+        Some(Command::Test { roms, timeout }) => {
+            let roms = roms.iter().flat_map(|path| {
+                if path.is_dir() {
+                    find_test_roms(path)
+                } else {
+                    vec![path.clone()]
+                }
+            });
+
+            let mut failures = 0;
+            for rom in roms {
+                let outcome = run_test_rom(rom.clone(), timeout.unwrap_or(DEFAULT_TIMEOUT));
+                match &outcome {
+                    Ok(outcome) => println!("{}: {outcome}", rom.display()),
+                    Err(e) => println!("{}: error: {e}", rom.display()),
+                }
+                if !matches!(outcome, Ok(TestOutcome::Passed)) {
+                    failures += 1;
+                }
+            }
+            if failures > 0 {
+                process::exit(1);
+            }
+        }
+        None => match &opt.rom {
+            Some(rom) => run(rom.clone(), &opt),
+            None => {
+                eprintln!("No ROM given, see --help");
+                process::exit(1);
+            }
+        },
+    }
+}
+
+fn run(rom: PathBuf, opt: &Opt) {
+    let mut config = Config {
+        model: opt.model,
+        ..Config::default()
+    };
+    if let Some(start) = opt.rtc_start {
+        config.clock = Box::new(CycleClock::new(start));
+    }
+    if let Some(path) = &opt.boot_rom {
+        match fs::read(path) {
+            Ok(data) => config.boot_rom = Some(data),
+            Err(e) => {
+                eprintln!("Could not read the boot ROM {}: {e}", path.display());
+                process::exit(1);
+            }
+        }
+    }
+
+    let link = match (&opt.link_listen, &opt.link_connect) {
+        (Some(addr), _) => {
+            eprintln!("Waiting for the link cable on {addr}");
+            Some(TcpLink::listen(addr.as_str(), opt.lockstep))
+        }
+        (None, Some(addr)) => Some(TcpLink::connect(addr.as_str(), opt.lockstep)),
+        (None, None) => None,
+    };
+    match link {
+        Some(Ok(link)) => config.serial = Box::new(link),
+        Some(Err(e)) => {
+            eprintln!("Could not plug the link cable: {e}");
+            process::exit(1);
+        }
+        None => (),
+    }
+    if let Some(dir) = &opt.printer {
+        config.serial = Box::new(Printer::new(dir.clone(), opt.printer_format, DMG_COLORS));
+    }
+    let mut peer = opt.link_rom.as_ref().map(|peer_rom| {
+        let (port, peer_port) = LinkPort::pair();
+        config.serial = Box::new(port);
+        let peer_config = Config {
+            serial: Box::new(peer_port),
+            ..Config::default()
+        };
+        match CPU::with_config(peer_rom.clone(), peer_config) {
+            Ok(peer) => peer,
+            Err(e) => {
+                eprintln!("Could not load {}: {e}", peer_rom.display());
+                process::exit(1);
+            }
+        }
+    });
+
+    let mut cpu = match CPU::with_config(rom.clone(), config) {
+        Ok(cpu) => cpu,
+        Err(e) => {
+            eprintln!("Could not load {}: {e}", rom.display());
+            process::exit(1);
+        }
+    };
+    cpu.mmu.set_autosave_interval(opt.autosave);
+    cpu.mmu.access_policy = opt.invalid_access;
+
+    let audio = AudioOutput::start();
+    match &audio {
+        Some(output) => cpu.mmu.apu.set_sample_rate(output.sample_rate),
+        None => eprintln!("No audio output device, running without sound"),
+    }
+
+    let mut window = Window::new(
+        "gb_emulator",
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        WindowOptions {
+            scale: Scale::X4,
+            ..WindowOptions::default()
+        },
+    )
+    .expect("Unable to open a window");
+    let mut buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut rumble = false;
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let frame_start = Instant::now();
+
+        for (key, button) in KEY_MAP {
+            if window.is_key_down(key) {
+                cpu.press(button);
+            } else {
+                cpu.release(button);
+            }
+        }
+
+        let result = match &mut peer {
+            Some(peer) => cpu.run_frame_linked(peer),
+            None => cpu.run_frame(),
+        };
+        if let Err(e) = result {
+            eprintln!("Emulation stopped: {e}");
+            break;
+        }
+
+        if cpu.rumble() != rumble {
+            rumble = !rumble;
+            window.set_title(if rumble {
+                "gb_emulator (rumble)"
+            } else {
+                "gb_emulator"
+            });
+        }
+
+        let samples = cpu.mmu.apu.take_samples();
+        if let Some(output) = &audio {
+            output.push(&samples);
+        }
+
+        for (pixel, color) in buffer.iter_mut().zip(cpu.mmu.ppu.frame_buffer()) {
+            *pixel = rgb555_to_rgb888(*color);
+        }
+        window
+            .update_with_buffer(&buffer)
+            .expect("Unable to draw the frame");
+
+        if let Some(remaining) = FRAME_DURATION.checked_sub(frame_start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+
+    for cpu in std::iter::once(&mut cpu).chain(&mut peer) {
+        if let Err(e) = cpu.flush_save() {
+            eprintln!("Could not write the save file: {e}");
+        }
+    }
 }
@@ -1,8 +1,22 @@
-use std::path::PathBuf;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    io,
+    path::PathBuf,
+};
 
 use crate::{
+    apu::{APU, CPU_FREQUENCY, DEFAULT_SAMPLE_RATE},
     cartridge::{get_mbc, MBC},
-    cpu::Mem,
+    config::Config,
+    cpu::{Bus, Mem},
+    dma::{Hdma, OamDma},
+    error::{AccessPolicy, EmulatorError},
+    interrupt::{Interrupt, InterruptController},
+    joypad::Joypad,
+    model::Model,
+    ppu::PPU,
+    serial::Serial,
     timer::Timer,
 };
 
@@ -10,6 +24,7 @@ use crate::{
 pub enum GbMode {
     Classic,
     Color,
+    /// CGB running a DMG cartridge
     ColorAsClassic,
 }
 
@@ -17,111 +32,606 @@ pub enum GbMode {
 /// https://gbdev.io/pandocs/Memory_Map.html
 pub struct MMU {
     pub mbc: Box<dyn MBC + 'static>,
-    vram: [u8; 0x2000],
+    /// mapped over the cartridge until 0xFF50 is written
+    boot_rom: Option<Vec<u8>>,
+    pub ppu: PPU,
+    pub apu: APU,
     wram: [u8; 0x8000],
+    /// CGB SVBK (0xFF70), bank mapped at 0xD000, always 1 outside of CGB mode
     wram_bank_idx: usize,
     timer: Timer,
-    oam: [u8; 0xA0],
+    oam_dma: OamDma,
+    hdma: Hdma,
+    /// T-cycles the CPU must stay paused for the VRAM DMA
+    dma_stall: u32,
+    pub joypad: Joypad,
+    pub serial: Serial,
     hram: [u8; 0x7F],
-    pub interrupt_enable: u8,
+    pub interrupt: InterruptController,
+    pub model: Model,
     pub mode: GbMode,
+    /// T-cycles elapsed since power on
+    pub cycles: u64,
+    /// CGB KEY1 bit 7
+    pub double_speed: bool,
+    /// CGB KEY1 bit 0, armed before a STOP to switch speed
+    speed_switch_armed: bool,
+    /// T-cycles at normal speed between two autosaves, 0 disables it
+    autosave_interval: u64,
+    autosave_timer: u64,
+    pub access_policy: AccessPolicy,
+    /// addresses already reported by `AccessPolicy::Log`
+    logged_accesses: RefCell<HashSet<u16>>,
+    /// error that must stop the emulator, set during an access with `&self`
+    fault: Cell<Option<EmulatorError>>,
 }
 
 impl MMU {
-    pub fn new(path: PathBuf) -> Self {
-        let mbc = match get_mbc(path) {
-            Ok(m) => m,
-            Err(s) => panic!("Error creating MMU: {s}"),
+    pub fn new(path: PathBuf, config: Config) -> Result<Self, EmulatorError> {
+        let mbc = get_mbc(path, config.clock)?;
+
+        let cgb_flag = mbc.read_rom(0x0143);
+        let model = config.model.unwrap_or_else(|| Model::detect(cgb_flag));
+        let mode = match (model.is_color(), cgb_flag & 0x80 != 0) {
+            (false, _) => GbMode::Classic,
+            (true, true) => GbMode::Color,
+            (true, false) => GbMode::ColorAsClassic,
         };
 
+        if let Some(boot_rom) = &config.boot_rom {
+            if boot_rom.len() != model.boot_rom_size() {
+                return Err(EmulatorError::InvalidBootRom(boot_rom.len()));
+            }
+        }
+
         let mut mmu = MMU {
             mbc,
-            vram: [0; 0x2000],
+            boot_rom: config.boot_rom,
+            ppu: PPU::new(mode == GbMode::Color),
+            apu: APU::new(DEFAULT_SAMPLE_RATE),
             wram: [0; 0x8000],
             wram_bank_idx: 1,
             timer: Timer::new(),
-            oam: [0; 0xA0],
+            oam_dma: OamDma::new(),
+            hdma: Hdma::new(),
+            dma_stall: 0,
+            joypad: Joypad::new(),
+            serial: Serial::new(config.serial, mode == GbMode::Color),
             hram: [0; 0x7F],
-            interrupt_enable: 0,
-            mode: GbMode::Classic,
+            interrupt: InterruptController::new(),
+            model,
+            mode,
+            cycles: 0,
+            double_speed: false,
+            speed_switch_armed: false,
+            autosave_interval: 0,
+            autosave_timer: 0,
+            access_policy: AccessPolicy::Log,
+            logged_accesses: RefCell::new(HashSet::new()),
+            fault: Cell::new(None),
         };
-        // mmu.initiate();
-        mmu
+        if !mmu.booting() {
+            mmu.skip_boot();
+        }
+        Ok(mmu)
+    }
+
+    /// The boot ROM is still mapped
+    pub fn booting(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    /// Set the I/O registers and VRAM as the boot ROM leaves them
+    /// https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+    fn skip_boot(&mut self) {
+        self.joypad.mem_write_u8(0xFF00, 0x00);
+        self.timer.set_system_counter(self.model.post_boot_div());
+        self.interrupt.mem_write_u8(0xFF0F, 0xE1);
+        if self.model.is_color() {
+            self.serial.mem_write_u8(0xFF02, 0x7F);
+        }
+
+        // sound: the boot chime was played on channel 1
+        self.apu.mem_write_u8(0xFF26, 0x80);
+        const APU_REGISTERS: [(u16, u8); 20] = [
+            (0xFF10, 0x80),
+            (0xFF11, 0xBF),
+            (0xFF12, 0xF3),
+            (0xFF13, 0xFF),
+            (0xFF14, 0xBF),
+            (0xFF16, 0x3F),
+            (0xFF17, 0x00),
+            (0xFF18, 0xFF),
+            (0xFF19, 0xBF),
+            (0xFF1A, 0x7F),
+            (0xFF1B, 0xFF),
+            (0xFF1C, 0x9F),
+            (0xFF1D, 0xFF),
+            (0xFF1E, 0xBF),
+            (0xFF20, 0xFF),
+            (0xFF21, 0x00),
+            (0xFF22, 0x00),
+            (0xFF23, 0xBF),
+            (0xFF24, 0x77),
+            (0xFF25, 0xF3),
+        ];
+        for (addr, data) in APU_REGISTERS {
+            self.apu.mem_write_u8(addr, data);
+        }
+
+        self.ppu.mem_write_u8(0xFF40, 0x91);
+        self.ppu.mem_write_u8(0xFF47, 0xFC);
+        self.ppu.mem_write_u8(0xFF48, 0xFF);
+        self.ppu.mem_write_u8(0xFF49, 0xFF);
+
+        if !self.model.is_color() {
+            self.load_logo();
+        }
+    }
+
+    /// The monochrome boot ROMs scroll the logo of the cartridge header down the screen,
+    /// its tiles and the tile map stay in VRAM
+    /// https://gbdev.io/pandocs/Power_Up_Sequence.html#monochrome-models-dmg0-dmg-mgb
+    fn load_logo(&mut self) {
+        // each nibble is a row of 4 pixels, doubled in both directions
+        let mut addr = 0x8010;
+        for i in 0x0104..=0x0133 {
+            let byte = self.mbc.read_rom(i);
+            for nibble in [byte >> 4, byte & 0x0F] {
+                let row = (0..4).fold(0u8, |row, bit| {
+                    row | (((nibble >> bit) & 1) * 0b11) << (bit * 2)
+                });
+                for _ in 0..2 {
+                    self.ppu.mem_write_u8(addr, row);
+                    addr += 2;
+                }
+            }
+        }
+
+        // the ® sign, stored in the boot ROM
+        const REGISTERED: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+        for row in REGISTERED {
+            self.ppu.mem_write_u8(addr, row);
+            addr += 2;
+        }
+
+        for tile in 0..12 {
+            self.ppu.mem_write_u8(0x9904 + tile, tile as u8 + 1);
+            self.ppu.mem_write_u8(0x9924 + tile, tile as u8 + 13);
+        }
+        self.ppu.mem_write_u8(0x9910, 0x19);
+    }
+
+    /// System scheduler: advance every component by `time` T-cycles
+    /// of the CPU clock, one M-cycle at a time
+    pub fn execute_cycle(&mut self, time: u32) {
+        for _ in 0..time / 4 {
+            self.execute_m_cycle();
+        }
+    }
+
+    fn execute_m_cycle(&mut self) {
+        self.cycles += 4;
+
+        // the PPU and APU keep their pace when the CPU runs in double speed
+        let normal_speed_time = if self.double_speed { 2 } else { 4 };
+
+        self.timer.execute_cycle(4);
+        self.serial.execute_cycle(4);
+        // one byte per M-cycle of the CPU, even in double speed
+        if let Some((source, destination)) = self.oam_dma.execute_m_cycle() {
+            let data = self.read(source);
+            self.oam_dma.last_byte = data;
+            self.ppu.mem_write_u8(destination, data);
+        }
+        self.ppu.execute_cycle(normal_speed_time);
+        if self.ppu.hblank_started {
+            self.ppu.hblank_started = false;
+            if self.hdma.hblank_active() {
+                self.hdma_copy_block();
+            }
+        }
+        self.apu.execute_cycle(normal_speed_time);
+        self.mbc.execute_cycle(normal_speed_time);
+
+        if self.autosave_interval > 0 {
+            self.autosave_timer += normal_speed_time as u64;
+            if self.autosave_timer >= self.autosave_interval {
+                self.autosave_timer = 0;
+                if let Err(e) = self.flush_save() {
+                    eprintln!("Autosave failed: {e}");
+                }
+            }
+        }
+
+        self.interrupt.request(self.timer.interrupt);
+        self.timer.interrupt = Interrupt::empty();
+        self.interrupt.request(self.ppu.interrupt);
+        self.ppu.interrupt = Interrupt::empty();
+        self.interrupt.request(self.joypad.interrupt);
+        self.joypad.interrupt = Interrupt::empty();
+        self.interrupt.request(self.serial.interrupt);
+        self.serial.interrupt = Interrupt::empty();
     }
 
-    fn initiate(&mut self) {
-        unimplemented!()
+    /// Copy 16 bytes to VRAM for the CGB DMA
+    fn hdma_copy_block(&mut self) {
+        let (source, destination) = self.hdma.next_block();
+        for i in 0..16 {
+            let data = self.read(source.wrapping_add(i));
+            self.ppu.mem_write_u8(destination + i, data);
+        }
+        // 8 M-cycles at normal speed, twice as many CPU cycles in double speed
+        self.dma_stall += 32 << self.double_speed as u32;
     }
 
-    fn execute_cycle(&mut self) {
-        unimplemented!()
+    /// T-cycles the CPU has to wait for the VRAM DMA, since the last call
+    pub fn take_dma_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.dma_stall)
+    }
+
+    /// Called by STOP, switch CGB speed if it was requested through KEY1
+    /// https://gbdev.io/pandocs/CGB_Registers.html#ff4d--key1-cgb-mode-only-prepare-speed-switch
+    pub fn try_switch_speed(&mut self) -> bool {
+        if self.mode != GbMode::Color || !self.speed_switch_armed {
+            return false;
+        }
+
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.reset_divider();
+        true
+    }
+
+    pub fn reset_divider(&mut self) {
+        self.timer.mem_write_u8(0xFF04, 0);
+    }
+
+    /// Write the cartridge RAM to the save file every `seconds` of emulated time,
+    /// 0 disables it
+    pub fn set_autosave_interval(&mut self, seconds: u64) {
+        self.autosave_interval = seconds * CPU_FREQUENCY as u64;
+        self.autosave_timer = 0;
+    }
+
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        self.mbc.flush()
+    }
+
+    /// Stop the emulator at the end of the current instruction
+    pub fn raise(&self, error: EmulatorError) {
+        // keep the first error, it's the cause of the others
+        let first = self.fault.take().unwrap_or(error);
+        self.fault.set(Some(first));
+    }
+
+    pub fn take_fault(&mut self) -> Option<EmulatorError> {
+        self.fault.take()
+    }
+
+    /// Access to unmapped memory or to a register of hardware that isn't
+    /// emulated, handled as `access_policy` says. Returns the value of an open bus read
+    fn invalid_access(&self, addr: u16, data: Option<u8>) -> u8 {
+        let error = EmulatorError::InvalidAccess { addr, data };
+        match self.access_policy {
+            // games clear whole regions in loops, one line per address is enough
+            AccessPolicy::Log => {
+                if self.logged_accesses.borrow_mut().insert(addr) {
+                    eprintln!("{error}");
+                }
+            }
+            AccessPolicy::Ignore => (),
+            AccessPolicy::Halt => self.raise(error),
+        }
+        0xFF
+    }
+
+    /// https://gbdev.io/pandocs/Memory_Map.html#fea0feff-range
+    fn unusable_read(&self, addr: u16) -> u8 {
+        match self.model {
+            Model::DMG0 | Model::DMG | Model::MGB | Model::SGB | Model::SGB2 => 0x00,
+            // CGB revision E and AGB: the upper nibble of the address, twice
+            Model::CGB | Model::AGB => {
+                let nibble = addr as u8 & 0xF0;
+                nibble | nibble >> 4
+            }
+        }
     }
 }
 
-impl Mem for MMU {
-    fn mem_read_u8(&self, addr: u16) -> u8 {
+/// save when the emulator is closed
+impl Drop for MMU {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_save() {
+            eprintln!("Could not write the save file: {e}");
+        }
+    }
+}
+
+impl MMU {
+    fn read(&self, addr: u16) -> u8 {
         match addr {
+            0x0000..=0x00FF | 0x0200..=0x08FF if self.booting() => {
+                let boot_rom = self.boot_rom.as_ref().unwrap();
+                match boot_rom.get(addr as usize) {
+                    Some(byte) => *byte,
+                    // a DMG boot ROM is only mapped up to 0x00FF
+                    None => self.mbc.read_rom(addr),
+                }
+            }
             0x0000..=0x7FFF => self.mbc.read_rom(addr),
-            0x8000..=0x9FFF => todo!("VRAM"),
+            0x8000..=0x9FFF => self.ppu.mem_read_u8(addr),
+            0xA000..=0xBFFF if self.mbc.rom_only() => self.invalid_access(addr, None),
             0xA000..=0xBFFF => self.mbc.read_ram(addr),
             0xC000..=0xCFFF => self.wram[addr as usize - 0xC000],
-            0xD000..=0xDFFF => self.wram[(self.wram_bank_idx * 0x1000) + (addr as usize - 0xC000)],
-            0xFE00..=0xFE9F => todo!("OAM"),
-            0xFF00 => todo!("Joypad input"),
-            0xFF01..=0xFF02 => unimplemented!("Serial transfer"),
-            0xFF04..=0xFF07 => self.timer.mem_read_u8(addr),
-            0xFF10..=0xFF26 => unimplemented!("Audio"),
-            0xFF30..=0xFF3F => unimplemented!("Wave pattern"),
-            0xFF40..=0xFF4B => {
-                unimplemented!("LCD Control, Status, Position, Scrolling, and Palettes")
-            }
-            0xFF4F => unimplemented!("VRAM Bank Select"),
-            0xFF50 => unimplemented!("Set to non-zero to disable boot ROM"),
-            0xFF51..=0xFF55 => unimplemented!("VRAM DMA"),
-            0xFF68..=0xFF69 => unimplemented!("BG / OBJ Palettes"),
-            0xFF70 => self.wram_bank_idx as u8,
-            0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
-            0xFFFF => self.interrupt_enable,
-            0xE000..=0xFDFF | 0xFEA0..=0xFEFF => {
-                panic!("Attempt to access prohibited memory region")
+            0xD000..=0xDFFF => self.wram[self.wram_bank_idx * 0x1000 + (addr as usize - 0xD000)],
+            // echo RAM mirrors 0xC000..=0xDDFF
+            0xE000..=0xFDFF => self.read(addr - 0x2000),
+            0xFE00..=0xFE9F => self.ppu.mem_read_u8(addr),
+            0xFEA0..=0xFEFF => {
+                self.invalid_access(addr, None);
+                self.unusable_read(addr)
             }
-            _ => {
-                panic!("Attempt to access unused memory region")
+            0xFF00 => self.joypad.mem_read_u8(addr),
+            0xFF01..=0xFF02 => self.serial.mem_read_u8(addr),
+            0xFF04..=0xFF07 => self.timer.mem_read_u8(addr),
+            0xFF0F => self.interrupt.mem_read_u8(addr),
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.mem_read_u8(addr),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.mem_read_u8(addr),
+            0xFF46 => self.oam_dma.register,
+            0xFF4D if self.mode == GbMode::Color => {
+                ((self.double_speed as u8) << 7) | 0b0111_1110 | self.speed_switch_armed as u8
             }
+            0xFF4F if self.mode == GbMode::Color => self.ppu.mem_read_u8(addr),
+            0xFF51..=0xFF55 if self.mode == GbMode::Color => self.hdma.read(addr),
+            0xFF68..=0xFF6B if self.mode == GbMode::Color => self.ppu.mem_read_u8(addr),
+            0xFF70 if self.mode == GbMode::Color => 0b1111_1000 | self.wram_bank_idx as u8,
+            0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
+            0xFFFF => self.interrupt.mem_read_u8(addr),
+            // unmapped I/O registers, and the infrared port (0xFF56) which isn't emulated yet
+            _ => self.invalid_access(addr, None),
         }
     }
 
-    fn mem_write_u8(&mut self, addr: u16, data: u8) {
+    fn write(&mut self, addr: u16, data: u8) {
         match addr {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF if self.mbc.rom_only() => {
+                self.invalid_access(addr, Some(data));
+            }
             0x0000..=0x7FFF => self.mbc.write_rom(addr, data),
-            0x8000..=0x9FFF => todo!("VRAM"),
+            0x8000..=0x9FFF => self.ppu.mem_write_u8(addr, data),
             0xA000..=0xBFFF => self.mbc.write_ram(addr, data),
             0xC000..=0xCFFF => self.wram[addr as usize - 0xC000] = data,
             0xD000..=0xDFFF => {
-                self.wram[(self.wram_bank_idx * 0x1000) + (addr as usize - 0xC000)] = data
+                self.wram[self.wram_bank_idx * 0x1000 + (addr as usize - 0xD000)] = data
             }
-            0xFE00..=0xFE9F => todo!("OAM"),
-            0xFF00 => todo!("Joypad input"),
-            0xFF01..=0xFF02 => unimplemented!("Serial transfer"),
+            0xE000..=0xFDFF => self.write(addr - 0x2000, data),
+            0xFE00..=0xFE9F => self.ppu.mem_write_u8(addr, data),
+            0xFF00 => self.joypad.mem_write_u8(addr, data),
+            0xFF01..=0xFF02 => self.serial.mem_write_u8(addr, data),
             0xFF04..=0xFF07 => self.timer.mem_write_u8(addr, data),
-            0xFF10..=0xFF26 => unimplemented!("Audio"),
-            0xFF30..=0xFF3F => unimplemented!("Wave pattern"),
-            0xFF40..=0xFF4B => {
-                unimplemented!("LCD Control, Status, Position, Scrolling, and Palettes")
-            }
-            0xFF4F => unimplemented!("VRAM Bank Select"),
-            0xFF50 => unimplemented!("Set to non-zero to disable boot ROM"),
-            0xFF51..=0xFF55 => unimplemented!("VRAM DMA"),
-            0xFF68..=0xFF69 => unimplemented!("BG / OBJ Palettes"),
-            0xFF70 => self.wram_bank_idx = data.max(1) as usize,
-            0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize] = data,
-            0xFFFF => self.interrupt_enable = data,
-            0xE000..=0xFDFF | 0xFEA0..=0xFEFF => {
-                panic!("Attempt to access prohibited memory region");
+            0xFF0F => self.interrupt.mem_write_u8(addr, data),
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.mem_write_u8(addr, data),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.mem_write_u8(addr, data),
+            0xFF46 => self.oam_dma.start(data),
+            0xFF4D if self.mode == GbMode::Color => self.speed_switch_armed = data & 0b1 == 1,
+            // unmaps the boot ROM, until the next reset
+            0xFF50 => {
+                if data != 0 {
+                    self.boot_rom = None;
+                }
+            }
+            0xFF4F if self.mode == GbMode::Color => self.ppu.mem_write_u8(addr, data),
+            0xFF51..=0xFF55 if self.mode == GbMode::Color => {
+                if let Some(blocks) = self.hdma.write(addr, data) {
+                    for _ in 0..blocks {
+                        self.hdma_copy_block();
+                    }
+                }
+            }
+            0xFF68..=0xFF6B if self.mode == GbMode::Color => self.ppu.mem_write_u8(addr, data),
+            // bank 0 selects bank 1
+            0xFF70 if self.mode == GbMode::Color => {
+                self.wram_bank_idx = (data & 0b111).max(1) as usize
             }
+            0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize] = data,
+            0xFFFF => self.interrupt.mem_write_u8(addr, data),
+            // unusable region, unmapped I/O registers and the infrared port
             _ => {
-                panic!("Attempt to access unused memory region")
+                self.invalid_access(addr, Some(data));
             }
         };
     }
 }
+
+impl Bus for MMU {
+    fn execute_cycle(&mut self, time: u32) {
+        MMU::execute_cycle(self, time);
+    }
+
+    fn interrupts(&mut self) -> &mut InterruptController {
+        &mut self.interrupt
+    }
+
+    fn joypad_pressed(&self) -> bool {
+        self.joypad.interrupt.contains(Interrupt::JOYPAD)
+    }
+
+    fn take_dma_stall(&mut self) -> u32 {
+        MMU::take_dma_stall(self)
+    }
+
+    fn try_switch_speed(&mut self) -> bool {
+        MMU::try_switch_speed(self)
+    }
+
+    fn reset_divider(&mut self) {
+        MMU::reset_divider(self);
+    }
+
+    fn raise(&self, error: EmulatorError) {
+        MMU::raise(self, error);
+    }
+}
+
+/// Accesses of the CPU, which competes with the OAM DMA for the buses
+impl Mem for MMU {
+    fn mem_read_u8(&self, addr: u16) -> u8 {
+        match self.oam_dma.conflict(addr) {
+            Some(data) => data,
+            None => self.read(addr),
+        }
+    }
+
+    fn mem_write_u8(&mut self, addr: u16, data: u8) {
+        if self.oam_dma.conflict(addr).is_none() {
+            self.write(addr, data);
+        }
+    }
+}
+
+#[test]
+fn test_boot_rom_mapping() {
+    let path = std::env::temp_dir().join("gb_emulator_test_boot_rom_mapping.gb");
+    let mut rom = vec![0x42; 0x8000];
+    rom[0x0147] = 0x00;
+    std::fs::write(&path, rom).unwrap();
+
+    let config = Config {
+        boot_rom: Some(vec![0x31; 0x100]),
+        ..Config::default()
+    };
+    let mut mmu = MMU::new(path.clone(), config).unwrap();
+    assert_eq!(mmu.mem_read_u8(0x00FF), 0x31);
+    assert_eq!(mmu.mem_read_u8(0x0100), 0x42);
+    assert_eq!(mmu.mem_read_u8(0x0200), 0x42);
+
+    mmu.mem_write_u8(0xFF50, 0x01);
+    assert_eq!(mmu.mem_read_u8(0x0000), 0x42);
+
+    // without a boot ROM, the post-boot state is set up
+    let mmu = MMU::new(path.clone(), Config::default()).unwrap();
+    assert_eq!(mmu.mem_read_u8(0xFF04), 0xAB);
+    assert_eq!(mmu.mem_read_u8(0xFF40), 0x91);
+    assert_eq!(mmu.mem_read_u8(0x9910), 0x19);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_invalid_access() {
+    let path = std::env::temp_dir().join("gb_emulator_test_invalid_access.gb");
+    std::fs::write(&path, vec![0; 0x8000]).unwrap();
+    let config = Config {
+        model: Some(Model::DMG),
+        ..Config::default()
+    };
+    let mut mmu = MMU::new(path.clone(), config).unwrap();
+    // setting up the post-boot state only touches mapped registers
+    assert!(mmu.logged_accesses.borrow().is_empty());
+
+    mmu.access_policy = AccessPolicy::Halt;
+    let accesses = [
+        (0xFF03, None),
+        (0xFF4D, None),
+        (0xA000, None),
+        (0xFEA0, None),
+        (0x2000, Some(0x01)),
+        (0xBFFF, Some(0x0A)),
+        (0xFEFF, Some(0x00)),
+        (0xFF7F, Some(0x00)),
+    ];
+    for (addr, data) in accesses {
+        match data {
+            None => {
+                mmu.mem_read_u8(addr);
+            }
+            Some(data) => mmu.mem_write_u8(addr, data),
+        }
+        let fault = mmu.take_fault();
+        assert!(
+            matches!(fault, Some(EmulatorError::InvalidAccess { addr: a, data: d }) if a == addr && d == data),
+            "{addr:04x}"
+        );
+    }
+    mmu.access_policy = AccessPolicy::Ignore;
+    assert_eq!(mmu.mem_read_u8(0xFF03), 0xFF);
+    // the unusable region still reads as on hardware
+    assert_eq!(mmu.mem_read_u8(0xFEA0), 0x00);
+    assert!(mmu.take_fault().is_none());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_oam_dma() {
+    let path = std::env::temp_dir().join("gb_emulator_test_oam_dma.gb");
+    std::fs::write(&path, vec![0; 0x8000]).unwrap();
+    let mut mmu = MMU::new(path.clone(), Config::default()).unwrap();
+
+    for i in 0..160 {
+        mmu.mem_write_u8(0xC000 + i, i as u8);
+    }
+    mmu.mem_write_u8(0xFF46, 0xC0);
+    mmu.execute_cycle(8);
+    assert_eq!(mmu.mem_read_u8(0xC010), 0x00);
+    assert_eq!(mmu.mem_read_u8(0xFE00), 0xFF);
+    mmu.mem_write_u8(0xFF80, 0x12);
+    assert_eq!(mmu.mem_read_u8(0xFF80), 0x12);
+
+    mmu.execute_cycle(640);
+    assert_eq!(mmu.mem_read_u8(0xFE9F), 159);
+    assert_eq!(mmu.mem_read_u8(0xFF46), 0xC0);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_cgb_banks_and_dma() {
+    let path = std::env::temp_dir().join("gb_emulator_test_cgb_banks_and_dma.gb");
+    let mut rom = vec![0; 0x8000];
+    rom[0x0143] = 0x80;
+    std::fs::write(&path, rom).unwrap();
+    let mut mmu = MMU::new(path.clone(), Config::default()).unwrap();
+
+    mmu.mem_write_u8(0xFF70, 0x02);
+    mmu.mem_write_u8(0xD000, 0x22);
+    mmu.mem_write_u8(0xFF70, 0x00);
+    assert_eq!(mmu.mem_read_u8(0xFF70), 0xF9);
+    assert_eq!(mmu.mem_read_u8(0xD000), 0x00);
+    mmu.mem_write_u8(0xFF70, 0x0A);
+    assert_eq!(mmu.mem_read_u8(0xD000), 0x22);
+
+    // general purpose DMA of 2 blocks to VRAM bank 1
+    for i in 0..32 {
+        mmu.mem_write_u8(0xC000 + i, i as u8 + 1);
+    }
+    mmu.mem_write_u8(0xFF4F, 0x01);
+    mmu.mem_write_u8(0xFF51, 0xC0);
+    mmu.mem_write_u8(0xFF52, 0x00);
+    mmu.mem_write_u8(0xFF53, 0x01);
+    mmu.mem_write_u8(0xFF54, 0x00);
+    mmu.mem_write_u8(0xFF55, 0x01);
+    assert_eq!(mmu.take_dma_stall(), 64);
+    assert_eq!(mmu.mem_read_u8(0xFF55), 0xFF);
+    assert_eq!(mmu.mem_read_u8(0x811F), 32);
+    mmu.mem_write_u8(0xFF4F, 0x00);
+    assert_eq!(mmu.mem_read_u8(0xFF4F), 0xFE);
+    assert_eq!(mmu.mem_read_u8(0x811F), 0x00);
+
+    // HBlank DMA: one block per line, the registers point past the last copy
+    mmu.mem_write_u8(0xFF51, 0xC0);
+    mmu.mem_write_u8(0xFF52, 0x00);
+    mmu.mem_write_u8(0xFF53, 0x01);
+    mmu.mem_write_u8(0xFF54, 0x00);
+    mmu.mem_write_u8(0xFF55, 0x81);
+    assert_eq!(mmu.mem_read_u8(0xFF55), 0x01);
+    mmu.execute_cycle(456);
+    assert_eq!(mmu.mem_read_u8(0xFF55), 0x00);
+    assert_eq!(mmu.mem_read_u8(0x810F), 0x10);
+    mmu.execute_cycle(456);
+    assert_eq!(mmu.mem_read_u8(0xFF55), 0xFF);
+    assert_eq!(mmu.take_dma_stall(), 64);
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,99 @@
+use std::str::FromStr;
+
+/// Game Boy hardware revisions
+/// https://gbdev.io/pandocs/Power_Up_Sequence.html
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Model {
+    /// early DMG, Japan only
+    DMG0,
+    DMG,
+    /// Game Boy Pocket and Light
+    MGB,
+    SGB,
+    SGB2,
+    CGB,
+    /// Game Boy Advance, in CGB mode
+    AGB,
+}
+
+impl Model {
+    /// Pick the console the cartridge was made for, from its CGB flag (0x0143)
+    pub fn detect(cgb_flag: u8) -> Self {
+        if cgb_flag & 0x80 != 0 {
+            Model::CGB
+        } else {
+            Model::DMG
+        }
+    }
+
+    /// Has the CGB hardware: VRAM and WRAM banks, color palettes, double speed...
+    pub fn is_color(self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+
+    /// Size of the boot ROM of this model
+    pub fn boot_rom_size(self) -> usize {
+        if self.is_color() {
+            0x900
+        } else {
+            0x100
+        }
+    }
+
+    /// Initial value of the 16-bit counter whose upper byte is DIV
+    pub fn post_boot_div(self) -> u16 {
+        match self {
+            Model::DMG0 => 0x1830,
+            Model::DMG | Model::MGB => 0xABCC,
+            Model::SGB | Model::SGB2 => 0xD84C,
+            Model::CGB | Model::AGB => 0x1EA0,
+        }
+    }
+
+    /// AF, BC, DE and HL as left by the boot ROM. `color_mode` is false when a CGB
+    /// runs a DMG cartridge, `header_checksum` is the byte at 0x014D
+    /// https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
+    pub fn post_boot_registers(self, color_mode: bool, header_checksum: u8) -> [u16; 4] {
+        // the DMG and MGB boot ROMs leave the flags of the header checksum verification
+        let flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+
+        match (self, color_mode) {
+            (Model::DMG0, _) => [0x0100, 0xFF13, 0x00C1, 0x8403],
+            (Model::DMG, _) => [0x0100 | flags, 0x0013, 0x00D8, 0x014D],
+            (Model::MGB, _) => [0xFF00 | flags, 0x0013, 0x00D8, 0x014D],
+            (Model::SGB, _) => [0x0100, 0x0014, 0x0000, 0xC060],
+            (Model::SGB2, _) => [0xFF00, 0x0014, 0x0000, 0xC060],
+            (Model::CGB, true) => [0x1180, 0x0000, 0xFF56, 0x000D],
+            (Model::CGB, false) => [0x1180, 0x0000, 0x0008, 0x007C],
+            (Model::AGB, true) => [0x1100, 0x0100, 0xFF56, 0x000D],
+            (Model::AGB, false) => [0x1100, 0x0100, 0x0008, 0x007C],
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg0" => Ok(Model::DMG0),
+            "dmg" => Ok(Model::DMG),
+            "mgb" => Ok(Model::MGB),
+            "sgb" => Ok(Model::SGB),
+            "sgb2" => Ok(Model::SGB2),
+            "cgb" => Ok(Model::CGB),
+            "agb" => Ok(Model::AGB),
+            _ => Err(format!(
+                "unknown model {s}, expected dmg0, dmg, mgb, sgb, sgb2, cgb or agb"
+            )),
+        }
+    }
+}
+
+#[test]
+fn test_post_boot_registers() {
+    assert_eq!(Model::DMG.post_boot_registers(false, 0x00)[0], 0x0180);
+    assert_eq!(Model::DMG.post_boot_registers(false, 0x3B)[0], 0x01B0);
+    assert_eq!(Model::CGB.post_boot_registers(true, 0x3B)[2], 0xFF56);
+    assert_eq!(Model::AGB.post_boot_registers(false, 0x3B)[1], 0x0100);
+}
@@ -1,5 +1,5 @@
 use crate::alu;
-use crate::cpu::{Mem, StatusFlags, CPU};
+use crate::cpu::{Bus, Mem, StatusFlags, CPU};
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 
@@ -532,7 +532,7 @@ lazy_static! {
     };
 }
 
-impl CPU {
+impl<M: Bus> CPU<M> {
     /// NOP
     #[allow(unused_variables)]
     fn op_0000(&mut self, op_size: u8) -> u8 {
@@ -3047,7 +3047,8 @@ impl CPU {
     #[allow(unused_variables)]
     fn op_00d9(&mut self, op_size: u8) -> u8 {
         self.program_counter = self.stack_pop();
-        self.enable_interrupt();
+        // unlike EI, RETI sets IME immediately
+        self.ime = true;
 
         16
     }
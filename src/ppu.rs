@@ -0,0 +1,741 @@
+use crate::{cpu::Mem, interrupt::Interrupt};
+use bitflags::bitflags;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+// https://gbdev.io/pandocs/Rendering.html
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+const HBLANK_DOTS: u32 = 204;
+const SCANLINE_DOTS: u32 = 456;
+const VBLANK_START_LINE: u8 = 144;
+const LAST_LINE: u8 = 153;
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+/// DMG shades in 15-bit RGB, from white to black
+pub const DMG_COLORS: [u16; 4] = [0x6BFC, 0x3B11, 0x29A6, 0x1061];
+
+bitflags! {
+    /// https://gbdev.io/pandocs/LCDC.html
+    ///
+    /// 7     bit     0
+    /// ----       ----
+    /// L W W B B O O B
+    /// | | | | | | | |
+    /// | | | | | | | +- BG and Window enable/priority
+    /// | | | | | | +--- OBJ enable
+    /// | | | | | +----- OBJ size (0=8x8, 1=8x16)
+    /// | | | | +------- BG tile map area (0=9800-9BFF, 1=9C00-9FFF)
+    /// | | | +--------- BG and Window tile data area (0=8800-97FF, 1=8000-8FFF)
+    /// | | +----------- Window enable
+    /// | +------------- Window tile map area (0=9800-9BFF, 1=9C00-9FFF)
+    /// +--------------- LCD and PPU enable
+    #[derive(Clone, Copy)]
+    pub struct LcdControl: u8 {
+        const BG_WINDOW_ENABLE = 0b0000_0001;
+        const OBJ_ENABLE = 0b0000_0010;
+        const OBJ_SIZE = 0b0000_0100;
+        const BG_TILE_MAP = 0b0000_1000;
+        const TILE_DATA = 0b0001_0000;
+        const WINDOW_ENABLE = 0b0010_0000;
+        const WINDOW_TILE_MAP = 0b0100_0000;
+        const LCD_ENABLE = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    /// https://gbdev.io/pandocs/STAT.html
+    /// Only the writable interrupt select bits are kept here,
+    /// the mode and the LYC == LY flag are computed on read
+    #[derive(Clone, Copy)]
+    pub struct LcdStatus: u8 {
+        const HBLANK_INT = 0b0000_1000;
+        const VBLANK_INT = 0b0001_0000;
+        const OAM_INT = 0b0010_0000;
+        const LYC_INT = 0b0100_0000;
+    }
+}
+
+bitflags! {
+    /// https://gbdev.io/pandocs/Tile_Maps.html#bg-map-attributes-cgb-mode-only
+    /// https://gbdev.io/pandocs/OAM.html#byte-3--attributesflags
+    ///
+    /// Layout shared by the CGB BG map attributes (VRAM bank 1) and the OBJ flags.
+    /// Bits 0-2 select the CGB palette
+    #[derive(Clone, Copy)]
+    struct Attributes: u8 {
+        const BANK = 0b0000_1000;
+        /// OBJ only, OBP1 instead of OBP0
+        const DMG_PALETTE = 0b0001_0000;
+        const X_FLIP = 0b0010_0000;
+        const Y_FLIP = 0b0100_0000;
+        /// BG over OBJ
+        const PRIORITY = 0b1000_0000;
+        const _ = 0b0000_0111;
+    }
+}
+
+impl Attributes {
+    fn palette(self) -> usize {
+        (self.bits() & 0b111) as usize
+    }
+
+    fn bank_offset(self) -> usize {
+        if self.contains(Attributes::BANK) {
+            0x2000
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum PpuMode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// Pixel Processing Unit
+/// https://gbdev.io/pandocs/Graphics.html
+pub struct PPU {
+    /// 2 banks on CGB, bank 1 holds extra tiles and the BG map attributes
+    vram: [u8; 0x4000],
+    /// CGB VBK (0xFF4F)
+    vram_bank: usize,
+    oam: [u8; 0xA0],
+    lcdc: LcdControl,
+    stat: LcdStatus,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    /// CGB mode: attribute maps, color palettes and CGB priorities
+    color_mode: bool,
+    /// CGB palette RAM, 8 palettes of 4 little-endian RGB555 colors
+    bg_palettes: [u8; 64],
+    obj_palettes: [u8; 64],
+    /// BCPS/OCPS: index into the palette RAM, bit 7 increments it after a write
+    bg_palette_index: u8,
+    obj_palette_index: u8,
+    mode: PpuMode,
+    dots: u32,
+    window_line: u8,
+    stat_line: bool,
+    /// 15-bit RGB colors of the last rendered frame
+    frame_buffer: [u16; SCREEN_WIDTH * SCREEN_HEIGHT],
+    pub frame_ready: bool,
+    /// HBlank just started on a visible line, time for a HBlank DMA transfer
+    pub hblank_started: bool,
+    pub interrupt: Interrupt,
+}
+
+impl PPU {
+    pub fn new(color_mode: bool) -> Self {
+        PPU {
+            vram: [0; 0x4000],
+            vram_bank: 0,
+            oam: [0; 0xA0],
+            lcdc: LcdControl::empty(),
+            stat: LcdStatus::empty(),
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            color_mode,
+            // the boot ROM sets every color to white
+            bg_palettes: [0xFF; 64],
+            obj_palettes: [0xFF; 64],
+            bg_palette_index: 0,
+            obj_palette_index: 0,
+            mode: PpuMode::HBlank,
+            dots: 0,
+            window_line: 0,
+            stat_line: false,
+            frame_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+            hblank_started: false,
+            interrupt: Interrupt::empty(),
+        }
+    }
+
+    pub fn frame_buffer(&self) -> &[u16] {
+        &self.frame_buffer
+    }
+
+    pub fn execute_cycle(&mut self, time: u32) {
+        if !self.lcdc.contains(LcdControl::LCD_ENABLE) {
+            return;
+        }
+
+        self.dots += time;
+
+        loop {
+            match self.mode {
+                PpuMode::OamScan if self.dots >= OAM_SCAN_DOTS => {
+                    self.dots -= OAM_SCAN_DOTS;
+                    self.set_mode(PpuMode::Drawing);
+                }
+                PpuMode::Drawing if self.dots >= DRAWING_DOTS => {
+                    self.dots -= DRAWING_DOTS;
+                    self.render_scanline();
+                    self.hblank_started = true;
+                    self.set_mode(PpuMode::HBlank);
+                }
+                PpuMode::HBlank if self.dots >= HBLANK_DOTS => {
+                    self.dots -= HBLANK_DOTS;
+                    self.ly += 1;
+
+                    if self.ly == VBLANK_START_LINE {
+                        self.window_line = 0;
+                        self.frame_ready = true;
+                        self.interrupt |= Interrupt::VBLANK;
+                        self.set_mode(PpuMode::VBlank);
+                    } else {
+                        self.set_mode(PpuMode::OamScan);
+                    }
+                }
+                PpuMode::VBlank if self.dots >= SCANLINE_DOTS => {
+                    self.dots -= SCANLINE_DOTS;
+
+                    if self.ly == LAST_LINE {
+                        self.ly = 0;
+                        self.set_mode(PpuMode::OamScan);
+                    } else {
+                        self.ly += 1;
+                        self.update_stat_interrupt();
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn set_mode(&mut self, mode: PpuMode) {
+        self.mode = mode;
+        self.update_stat_interrupt();
+    }
+
+    /// The STAT interrupt is only requested on a rising edge of the
+    /// OR-ed interrupt sources ("STAT blocking")
+    fn update_stat_interrupt(&mut self) {
+        if !self.lcdc.contains(LcdControl::LCD_ENABLE) {
+            return;
+        }
+
+        let line = (self.stat.contains(LcdStatus::LYC_INT) && self.ly == self.lyc)
+            || (self.stat.contains(LcdStatus::HBLANK_INT) && self.mode == PpuMode::HBlank)
+            || (self.stat.contains(LcdStatus::VBLANK_INT) && self.mode == PpuMode::VBlank)
+            || (self.stat.contains(LcdStatus::OAM_INT) && self.mode == PpuMode::OamScan);
+
+        if line && !self.stat_line {
+            self.interrupt |= Interrupt::LCD_STAT;
+        }
+        self.stat_line = line;
+    }
+
+    fn write_lcdc(&mut self, data: u8) {
+        let was_enabled = self.lcdc.contains(LcdControl::LCD_ENABLE);
+        self.lcdc = LcdControl::from_bits_truncate(data);
+
+        match (was_enabled, self.lcdc.contains(LcdControl::LCD_ENABLE)) {
+            (true, false) => {
+                self.ly = 0;
+                self.dots = 0;
+                self.window_line = 0;
+                self.mode = PpuMode::HBlank;
+                self.stat_line = false;
+            }
+            (false, true) => {
+                self.dots = 0;
+                self.set_mode(PpuMode::OamScan);
+            }
+            _ => (),
+        }
+    }
+
+    //* Rendering *//
+
+    fn render_scanline(&mut self) {
+        // color indices (before palette) of BG/window, needed for sprite priority
+        let mut bg_colors = [0_u8; SCREEN_WIDTH];
+        // CGB BG map attribute priority bit
+        let mut bg_priority = [false; SCREEN_WIDTH];
+
+        // on CGB, LCDC.0 only takes the priority away from the BG
+        if self.color_mode || self.lcdc.contains(LcdControl::BG_WINDOW_ENABLE) {
+            self.render_background(&mut bg_colors, &mut bg_priority);
+        } else {
+            let line = self.ly as usize * SCREEN_WIDTH;
+            self.frame_buffer[line..line + SCREEN_WIDTH].fill(DMG_COLORS[0]);
+        }
+
+        if self.lcdc.contains(LcdControl::OBJ_ENABLE) {
+            self.render_sprites(&bg_colors, &bg_priority);
+        }
+    }
+
+    fn render_background(
+        &mut self,
+        bg_colors: &mut [u8; SCREEN_WIDTH],
+        bg_priority: &mut [bool; SCREEN_WIDTH],
+    ) {
+        let ly = self.ly;
+        let window_x = self.wx as i16 - 7;
+        let window_visible = self.lcdc.contains(LcdControl::WINDOW_ENABLE)
+            && self.wy <= ly
+            && window_x < SCREEN_WIDTH as i16;
+
+        for x in 0..SCREEN_WIDTH {
+            let in_window = window_visible && x as i16 >= window_x;
+
+            let (map_base, map_x, map_y) = if in_window {
+                let map = if self.lcdc.contains(LcdControl::WINDOW_TILE_MAP) {
+                    0x1C00
+                } else {
+                    0x1800
+                };
+                (map, (x as i16 - window_x) as u8, self.window_line)
+            } else {
+                let map = if self.lcdc.contains(LcdControl::BG_TILE_MAP) {
+                    0x1C00
+                } else {
+                    0x1800
+                };
+                (
+                    map,
+                    self.scx.wrapping_add(x as u8),
+                    self.scy.wrapping_add(ly),
+                )
+            };
+
+            let tile_idx = map_base + (map_y as usize / 8) * 32 + (map_x as usize / 8);
+            let attributes = if self.color_mode {
+                Attributes::from_bits_retain(self.vram[0x2000 + tile_idx])
+            } else {
+                Attributes::empty()
+            };
+            let tile_addr = self.tile_data_addr(self.vram[tile_idx]) + attributes.bank_offset();
+
+            let (mut col, mut row) = (map_x % 8, map_y % 8);
+            if attributes.contains(Attributes::X_FLIP) {
+                col = 7 - col;
+            }
+            if attributes.contains(Attributes::Y_FLIP) {
+                row = 7 - row;
+            }
+            let color = self.tile_pixel(tile_addr, col, row);
+
+            bg_colors[x] = color;
+            bg_priority[x] = attributes.contains(Attributes::PRIORITY);
+            self.frame_buffer[ly as usize * SCREEN_WIDTH + x] = if self.color_mode {
+                cgb_color(&self.bg_palettes, attributes.palette(), color)
+            } else {
+                DMG_COLORS[palette_shade(self.bgp, color) as usize]
+            };
+        }
+
+        if window_visible {
+            self.window_line += 1;
+        }
+    }
+
+    fn render_sprites(
+        &mut self,
+        bg_colors: &[u8; SCREEN_WIDTH],
+        bg_priority: &[bool; SCREEN_WIDTH],
+    ) {
+        let ly = self.ly as i16;
+        let height: i16 = if self.lcdc.contains(LcdControl::OBJ_SIZE) {
+            16
+        } else {
+            8
+        };
+
+        // https://gbdev.io/pandocs/OAM.html#selection-priority
+        let mut sprites: Vec<usize> = (0..40)
+            .filter(|i| {
+                let y = self.oam[i * 4] as i16 - 16;
+                ly >= y && ly < y + height
+            })
+            .take(MAX_SPRITES_PER_LINE)
+            .collect();
+
+        // lower X wins, then lower OAM index: draw the winners last.
+        // On CGB only the OAM index counts
+        if !self.color_mode {
+            sprites.sort_by_key(|&i| (self.oam[i * 4 + 1], i));
+        }
+
+        for &i in sprites.iter().rev() {
+            let y = self.oam[i * 4] as i16 - 16;
+            let x = self.oam[i * 4 + 1] as i16 - 8;
+            let mut tile = self.oam[i * 4 + 2];
+            let flags = Attributes::from_bits_retain(self.oam[i * 4 + 3]);
+
+            let behind_bg = flags.contains(Attributes::PRIORITY);
+            let palette = if flags.contains(Attributes::DMG_PALETTE) {
+                self.obp1
+            } else {
+                self.obp0
+            };
+
+            let mut row = (ly - y) as u8;
+            if flags.contains(Attributes::Y_FLIP) {
+                row = height as u8 - 1 - row;
+            }
+            if height == 16 {
+                tile = (tile & 0xFE) + row / 8;
+                row %= 8;
+            }
+            let tile_addr = tile as usize * 16
+                + if self.color_mode {
+                    flags.bank_offset()
+                } else {
+                    0
+                };
+
+            for px in 0..8_i16 {
+                let screen_x = x + px;
+                if !(0..SCREEN_WIDTH as i16).contains(&screen_x) {
+                    continue;
+                }
+
+                let col = if flags.contains(Attributes::X_FLIP) {
+                    7 - px
+                } else {
+                    px
+                } as u8;
+                let color = self.tile_pixel(tile_addr, col, row);
+                if color == 0 {
+                    continue;
+                }
+
+                // https://gbdev.io/pandocs/Tile_Maps.html#bg-to-obj-priority-in-cgb-mode
+                let bg_color = bg_colors[screen_x as usize];
+                let hidden = if self.color_mode {
+                    bg_color != 0
+                        && self.lcdc.contains(LcdControl::BG_WINDOW_ENABLE)
+                        && (behind_bg || bg_priority[screen_x as usize])
+                } else {
+                    behind_bg && bg_color != 0
+                };
+                if hidden {
+                    continue;
+                }
+
+                self.frame_buffer[ly as usize * SCREEN_WIDTH + screen_x as usize] =
+                    if self.color_mode {
+                        cgb_color(&self.obj_palettes, flags.palette(), color)
+                    } else {
+                        DMG_COLORS[palette_shade(palette, color) as usize]
+                    };
+            }
+        }
+    }
+
+    /// Offset into VRAM of the tile with index `tile` for BG and window,
+    /// using the addressing mode selected by LCDC.4
+    fn tile_data_addr(&self, tile: u8) -> usize {
+        if self.lcdc.contains(LcdControl::TILE_DATA) {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8 as i32) * 16) as usize
+        }
+    }
+
+    /// 2-bit color index of pixel (x, y) inside the tile at `tile_addr`
+    /// https://gbdev.io/pandocs/Tile_Data.html
+    fn tile_pixel(&self, tile_addr: usize, x: u8, y: u8) -> u8 {
+        let lo = self.vram[tile_addr + y as usize * 2];
+        let hi = self.vram[tile_addr + y as usize * 2 + 1];
+        let bit = 7 - x;
+        (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1)
+    }
+}
+
+/// https://gbdev.io/pandocs/Palettes.html
+fn palette_shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0b11
+}
+
+/// Expand a 15-bit color of the PPU to 0RGB
+pub fn rgb555_to_rgb888(color: u16) -> u32 {
+    let channel = |shift: u16| {
+        let c = ((color >> shift) & 0x1F) as u32;
+        (c << 3) | (c >> 2)
+    };
+    (channel(0) << 16) | (channel(5) << 8) | channel(10)
+}
+
+/// RGB555 value of `color` in one of the 8 CGB palettes
+/// https://gbdev.io/pandocs/Palettes.html#lcd-color-palettes-cgb-only
+fn cgb_color(palettes: &[u8; 64], palette: usize, color: u8) -> u16 {
+    let offset = palette * 8 + color as usize * 2;
+    u16::from_le_bytes([palettes[offset], palettes[offset + 1]]) & 0x7FFF
+}
+
+/// Write to BCPD/OCPD at the index of BCPS/OCPS, which may increment
+fn write_palette(palettes: &mut [u8; 64], index: &mut u8, data: u8) {
+    palettes[(*index & 0x3F) as usize] = data;
+    if *index & 0x80 != 0 {
+        *index = 0x80 | (index.wrapping_add(1) & 0x3F);
+    }
+}
+
+impl Mem for PPU {
+    fn mem_read_u8(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.vram[self.vram_bank * 0x2000 + addr as usize - 0x8000],
+            0xFE00..=0xFE9F => self.oam[addr as usize - 0xFE00],
+            0xFF40 => self.lcdc.bits(),
+            0xFF41 => {
+                let mode = if self.lcdc.contains(LcdControl::LCD_ENABLE) {
+                    self.mode as u8
+                } else {
+                    0
+                };
+                let coincidence = ((self.ly == self.lyc) as u8) << 2;
+                0b1000_0000 | self.stat.bits() | coincidence | mode
+            }
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            0xFF4F => 0b1111_1110 | self.vram_bank as u8,
+            0xFF68 => 0b0100_0000 | self.bg_palette_index,
+            0xFF69 => self.bg_palettes[(self.bg_palette_index & 0x3F) as usize],
+            0xFF6A => 0b0100_0000 | self.obj_palette_index,
+            0xFF6B => self.obj_palettes[(self.obj_palette_index & 0x3F) as usize],
+            _ => panic!("PPU can't read {addr:4x}"),
+        }
+    }
+
+    fn mem_write_u8(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.vram[self.vram_bank * 0x2000 + addr as usize - 0x8000] = data,
+            0xFE00..=0xFE9F => self.oam[addr as usize - 0xFE00] = data,
+            0xFF40 => self.write_lcdc(data),
+            0xFF41 => {
+                self.stat = LcdStatus::from_bits_truncate(data);
+                self.update_stat_interrupt();
+            }
+            0xFF42 => self.scy = data,
+            0xFF43 => self.scx = data,
+            0xFF44 => (), // LY is read-only
+            0xFF45 => {
+                self.lyc = data;
+                self.update_stat_interrupt();
+            }
+            0xFF47 => self.bgp = data,
+            0xFF48 => self.obp0 = data,
+            0xFF49 => self.obp1 = data,
+            0xFF4A => self.wy = data,
+            0xFF4B => self.wx = data,
+            0xFF4F => self.vram_bank = data as usize & 0b1,
+            0xFF68 => self.bg_palette_index = data & 0b1011_1111,
+            0xFF69 => write_palette(&mut self.bg_palettes, &mut self.bg_palette_index, data),
+            0xFF6A => self.obj_palette_index = data & 0b1011_1111,
+            0xFF6B => write_palette(&mut self.obj_palettes, &mut self.obj_palette_index, data),
+            _ => panic!("PPU can't write {addr:4x}"),
+        }
+    }
+}
+
+#[test]
+fn test_cgb_palettes() {
+    let mut ppu = PPU::new(true);
+    ppu.mem_write_u8(0xFF68, 0x80 | 0x3E);
+    ppu.mem_write_u8(0xFF69, 0x1F);
+    ppu.mem_write_u8(0xFF69, 0x00);
+    ppu.mem_write_u8(0xFF69, 0xE0);
+    assert_eq!(ppu.mem_read_u8(0xFF68), 0xC1);
+    assert_eq!(cgb_color(&ppu.bg_palettes, 7, 3), 0x001F);
+    assert_eq!(cgb_color(&ppu.bg_palettes, 0, 0), 0x7FE0);
+
+    // no increment on reads
+    ppu.mem_write_u8(0xFF6A, 0x02);
+    ppu.mem_write_u8(0xFF6B, 0x12);
+    assert_eq!(ppu.mem_read_u8(0xFF6B), 0x12);
+    assert_eq!(ppu.mem_read_u8(0xFF6A), 0x42);
+}
+
+#[cfg(test)]
+fn mode(ppu: &PPU) -> u8 {
+    ppu.mem_read_u8(0xFF41) & 0b11
+}
+
+#[test]
+fn test_scanline_timing() {
+    let mut ppu = PPU::new(false);
+    ppu.mem_write_u8(0xFF40, 0x80);
+    assert_eq!(mode(&ppu), 2);
+
+    ppu.execute_cycle(OAM_SCAN_DOTS - 4);
+    assert_eq!(mode(&ppu), 2);
+    ppu.execute_cycle(4);
+    assert_eq!(mode(&ppu), 3);
+    ppu.execute_cycle(DRAWING_DOTS);
+    assert_eq!(mode(&ppu), 0);
+    ppu.execute_cycle(HBLANK_DOTS - 4);
+    assert_eq!((mode(&ppu), ppu.mem_read_u8(0xFF44)), (0, 0));
+    ppu.execute_cycle(4);
+    assert_eq!((mode(&ppu), ppu.mem_read_u8(0xFF44)), (2, 1));
+
+    ppu.execute_cycle(143 * SCANLINE_DOTS);
+    assert_eq!((mode(&ppu), ppu.mem_read_u8(0xFF44)), (1, 144));
+    assert!(ppu.frame_ready);
+    assert!(ppu.interrupt == Interrupt::VBLANK);
+
+    // 10 lines of VBlank
+    ppu.execute_cycle(9 * SCANLINE_DOTS);
+    assert_eq!((mode(&ppu), ppu.mem_read_u8(0xFF44)), (1, 153));
+    ppu.execute_cycle(SCANLINE_DOTS);
+    assert_eq!((mode(&ppu), ppu.mem_read_u8(0xFF44)), (2, 0));
+    assert_eq!(154 * SCANLINE_DOTS, CYCLES_PER_FRAME);
+}
+
+#[test]
+fn test_stat_interrupt_edge() {
+    let mut ppu = PPU::new(false);
+    ppu.mem_write_u8(0xFF40, 0x80);
+    ppu.mem_write_u8(0xFF41, (LcdStatus::LYC_INT | LcdStatus::HBLANK_INT).bits());
+    assert_eq!(ppu.mem_read_u8(0xFF41) & 0b100, 0b100);
+    assert!(ppu.interrupt == Interrupt::LCD_STAT);
+    ppu.interrupt = Interrupt::empty();
+
+    // the line is already high
+    ppu.mem_write_u8(0xFF45, 0);
+    ppu.execute_cycle(OAM_SCAN_DOTS + DRAWING_DOTS);
+    assert!(ppu.interrupt.is_empty());
+
+    // LY=1 drops it, HBlank raises it again
+    ppu.execute_cycle(HBLANK_DOTS + OAM_SCAN_DOTS);
+    assert!(ppu.interrupt.is_empty());
+    ppu.execute_cycle(DRAWING_DOTS);
+    assert!(ppu.interrupt == Interrupt::LCD_STAT);
+    ppu.interrupt = Interrupt::empty();
+
+    // LY=LYC takes over from HBlank without the line going low: no interrupt
+    ppu.mem_write_u8(0xFF45, 2);
+    ppu.execute_cycle(HBLANK_DOTS);
+    assert_eq!(ppu.mem_read_u8(0xFF44), 2);
+    assert!(ppu.interrupt.is_empty());
+}
+
+#[test]
+fn test_window_line_counter() {
+    let mut ppu = PPU::new(false);
+    // window on the 0x9800 map: tile 0 (color 0) on the first row, tile 1 (color 3) on the second
+    ppu.vram[0x10..0x20].fill(0xFF);
+    ppu.vram[0x1820..0x1840].fill(1);
+    ppu.mem_write_u8(0xFF47, 0xE4);
+    ppu.mem_write_u8(0xFF4B, 7);
+    ppu.mem_write_u8(0xFF40, 0xB9);
+
+    ppu.execute_cycle(8 * SCANLINE_DOTS);
+    assert_eq!(ppu.window_line, 8);
+
+    // hiding the window pauses its line counter
+    ppu.mem_write_u8(0xFF4B, 167);
+    ppu.execute_cycle(8 * SCANLINE_DOTS);
+    assert_eq!(ppu.window_line, 8);
+
+    ppu.mem_write_u8(0xFF4B, 7);
+    ppu.execute_cycle(SCANLINE_DOTS);
+    assert_eq!(ppu.frame_buffer()[16 * SCREEN_WIDTH], DMG_COLORS[3]);
+    assert_eq!(ppu.window_line, 9);
+
+    // reset at VBlank
+    ppu.execute_cycle(130 * SCANLINE_DOTS);
+    assert_eq!(ppu.window_line, 0);
+}
+
+#[test]
+fn test_sprites() {
+    let mut ppu = PPU::new(false);
+    ppu.vram[0x10..0x20].fill(0xFF);
+    ppu.mem_write_u8(0xFF48, 0xE4);
+    ppu.mem_write_u8(0xFF49, 0x54);
+    // 11 sprites of tile 1 side by side on the first line
+    for i in 0..11 {
+        let sprite = [16, 8 + 8 * i, 1, 0];
+        ppu.oam[i as usize * 4..i as usize * 4 + 4].copy_from_slice(&sprite);
+    }
+    ppu.mem_write_u8(0xFF40, 0x82);
+    ppu.execute_cycle(SCANLINE_DOTS);
+    assert_eq!(ppu.frame_buffer()[72], DMG_COLORS[3]);
+    assert_eq!(ppu.frame_buffer()[80], DMG_COLORS[0]);
+
+    // on line 1, sprite 1 with OBP1 overlaps sprite 0 from the left: the lower X wins on DMG
+    ppu.oam.fill(0);
+    ppu.oam[..8].copy_from_slice(&[17, 12, 1, 0, 17, 8, 1, 0x10]);
+    ppu.execute_cycle(SCANLINE_DOTS);
+    assert_eq!(ppu.frame_buffer()[SCREEN_WIDTH + 4], DMG_COLORS[1]);
+    assert_eq!(ppu.frame_buffer()[SCREEN_WIDTH + 8], DMG_COLORS[3]);
+
+    // on CGB the lower OAM index wins
+    let mut ppu = PPU::new(true);
+    ppu.vram[0x10..0x20].fill(0xFF);
+    ppu.mem_write_u8(0xFF6A, 0x80 | 0x0E);
+    ppu.mem_write_u8(0xFF6B, 0x1F);
+    ppu.mem_write_u8(0xFF6B, 0x00);
+    ppu.oam[..8].copy_from_slice(&[16, 12, 1, 1, 16, 8, 1, 0]);
+    ppu.mem_write_u8(0xFF40, 0x82);
+    ppu.execute_cycle(SCANLINE_DOTS);
+    assert_eq!(ppu.frame_buffer()[4], 0x001F);
+    assert_eq!(ppu.frame_buffer()[0], 0x7FFF);
+}
+
+#[test]
+fn test_palette_mapping() {
+    let mut ppu = PPU::new(false);
+    // tile 0 starts with colors 0, 1, 2, 3
+    ppu.vram[0] = 0b0101_0000;
+    ppu.vram[1] = 0b0011_0000;
+    ppu.mem_write_u8(0xFF47, 0x1B);
+    ppu.mem_write_u8(0xFF49, 0xE4);
+    // over the second tile, OBP1
+    ppu.oam[..4].copy_from_slice(&[16, 16, 0, 0x10]);
+    ppu.mem_write_u8(0xFF40, 0x93);
+    ppu.execute_cycle(SCANLINE_DOTS);
+    assert_eq!(
+        ppu.frame_buffer()[..4],
+        [DMG_COLORS[3], DMG_COLORS[2], DMG_COLORS[1], DMG_COLORS[0]]
+    );
+    // color 0 of a sprite is transparent
+    assert_eq!(ppu.frame_buffer()[8], DMG_COLORS[3]);
+    assert_eq!(ppu.frame_buffer()[9], DMG_COLORS[1]);
+    assert_eq!(ppu.frame_buffer()[11], DMG_COLORS[3]);
+
+    // CGB: BG palette 2 from the attribute map in bank 1
+    let mut ppu = PPU::new(true);
+    ppu.vram[0] = 0b0101_0000;
+    ppu.vram[1] = 0b0011_0000;
+    ppu.mem_write_u8(0xFF4F, 1);
+    ppu.mem_write_u8(0x9800, 2);
+    ppu.mem_write_u8(0xFF68, 0x80 | 0x12);
+    ppu.mem_write_u8(0xFF69, 0x00);
+    ppu.mem_write_u8(0xFF69, 0x7C);
+    ppu.mem_write_u8(0xFF40, 0x91);
+    ppu.execute_cycle(SCANLINE_DOTS);
+    assert_eq!(ppu.frame_buffer()[..3], [0x7FFF, 0x7C00, 0x7FFF]);
+}
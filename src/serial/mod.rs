@@ -0,0 +1,235 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{cpu::Mem, interrupt::Interrupt};
+use bitflags::bitflags;
+
+pub mod printer;
+pub mod tcp;
+
+/// T-cycles to shift one bit with the internal clock (8192 Hz)
+const BIT_CYCLES: u32 = 512;
+/// T-cycles to shift one bit with the CGB fast clock (262144 Hz)
+const FAST_BIT_CYCLES: u32 = 16;
+
+bitflags! {
+    /// https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html#ff02--sc-serial-transfer-control
+    #[derive(Clone, Copy)]
+    pub struct SerialControl: u8 {
+        /// this Game Boy drives the clock
+        const INTERNAL_CLOCK = 0b0000_0001;
+        /// CGB only, 32 times faster
+        const FAST_CLOCK = 0b0000_0010;
+        const TRANSFER = 0b1000_0000;
+    }
+}
+
+/// What is plugged into the link port
+pub trait SerialDevice {
+    /// Called when this Game Boy clocks a transfer: `data` is shifted out,
+    /// the returned byte is shifted in
+    fn exchange(&mut self, data: u8) -> u8;
+
+    /// Called every M-cycle while this Game Boy waits for the device to clock a
+    /// transfer, with `data` in SB. Returns the received byte once it did
+    fn poll(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+
+    /// Called every M-cycle with the T-cycles elapsed
+    fn execute_cycle(&mut self, _time: u32) {}
+}
+
+/// Nothing plugged in: the data line is pulled up and no external clock comes
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn exchange(&mut self, _data: u8) -> u8 {
+        0xFF
+    }
+}
+
+/// Record every byte sent, like a disconnected cable otherwise.
+/// Clones share the same buffer, so one can stay outside of the emulator
+#[derive(Clone, Default)]
+pub struct Capture {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn output(&self) -> Vec<u8> {
+        self.buffer.borrow().clone()
+    }
+}
+
+impl SerialDevice for Capture {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.buffer.borrow_mut().push(data);
+        0xFF
+    }
+}
+
+/// Both ends of a cable between two emulators of the same process
+#[derive(Default)]
+pub struct Cable {
+    /// SB of each side while it waits for an external clock
+    waiting: [Option<u8>; 2],
+    /// byte clocked in by the other side, not yet seen by this one
+    received: [Option<u8>; 2],
+}
+
+/// One end of a link cable to another emulator instance, made by `LinkPort::pair`
+pub struct LinkPort {
+    cable: Rc<RefCell<Cable>>,
+    side: usize,
+}
+
+impl LinkPort {
+    pub fn pair() -> (LinkPort, LinkPort) {
+        let cable = Rc::new(RefCell::new(Cable::default()));
+        (
+            LinkPort {
+                cable: cable.clone(),
+                side: 0,
+            },
+            LinkPort { cable, side: 1 },
+        )
+    }
+}
+
+impl SerialDevice for LinkPort {
+    fn exchange(&mut self, data: u8) -> u8 {
+        let mut cable = self.cable.borrow_mut();
+        let other = 1 - self.side;
+        // the other side only shifts if it's waiting for our clock
+        match cable.waiting[other].take() {
+            Some(received) => {
+                cable.received[other] = Some(data);
+                received
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn poll(&mut self, data: u8) -> Option<u8> {
+        let mut cable = self.cable.borrow_mut();
+        match cable.received[self.side].take() {
+            Some(received) => Some(received),
+            None => {
+                cable.waiting[self.side] = Some(data);
+                None
+            }
+        }
+    }
+}
+
+/// https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+///
+/// SB (0xFF01) is shifted out while the other side's byte is shifted in,
+/// the interrupt is requested once the 8 bits are exchanged.
+pub struct Serial {
+    data: u8,
+    control: SerialControl,
+    /// the fast clock exists
+    color_mode: bool,
+    /// T-cycles left in the transfer, when this side drives the clock
+    remaining: u32,
+    pub device: Box<dyn SerialDevice>,
+    pub interrupt: Interrupt,
+}
+
+impl Serial {
+    pub fn new(device: Box<dyn SerialDevice>, color_mode: bool) -> Self {
+        Serial {
+            data: 0,
+            control: SerialControl::empty(),
+            color_mode,
+            remaining: 0,
+            device,
+            interrupt: Interrupt::empty(),
+        }
+    }
+
+    /// `time` is in T-cycles of the CPU clock, the serial clock follows double speed
+    pub fn execute_cycle(&mut self, time: u32) {
+        self.device.execute_cycle(time);
+        if !self.control.contains(SerialControl::TRANSFER) {
+            return;
+        }
+
+        if self.control.contains(SerialControl::INTERNAL_CLOCK) {
+            self.remaining = self.remaining.saturating_sub(time);
+            if self.remaining == 0 {
+                let received = self.device.exchange(self.data);
+                self.finish(received);
+            }
+        } else if let Some(received) = self.device.poll(self.data) {
+            self.finish(received);
+        }
+    }
+
+    fn finish(&mut self, received: u8) {
+        self.data = received;
+        self.control.remove(SerialControl::TRANSFER);
+        self.interrupt |= Interrupt::SERIAL;
+    }
+}
+
+impl Mem for Serial {
+    fn mem_read_u8(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.data,
+            0xFF02 => {
+                let unused = if self.color_mode {
+                    0b0111_1100
+                } else {
+                    0b0111_1110
+                };
+                unused | self.control.bits()
+            }
+            _ => panic!("Serial can't read {addr:4x}"),
+        }
+    }
+
+    fn mem_write_u8(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xFF01 => self.data = data,
+            0xFF02 => {
+                self.control = SerialControl::from_bits_truncate(data);
+                if !self.color_mode {
+                    self.control.remove(SerialControl::FAST_CLOCK);
+                }
+                let bit_cycles = if self.control.contains(SerialControl::FAST_CLOCK) {
+                    FAST_BIT_CYCLES
+                } else {
+                    BIT_CYCLES
+                };
+                self.remaining = 8 * bit_cycles;
+            }
+            _ => panic!("Serial can't write {addr:4x}"),
+        }
+    }
+}
+
+#[test]
+fn test_link_port() {
+    let (a, b) = LinkPort::pair();
+    let mut master = Serial::new(Box::new(a), false);
+    let mut slave = Serial::new(Box::new(b), false);
+
+    slave.mem_write_u8(0xFF01, 0x42);
+    slave.mem_write_u8(0xFF02, 0x80);
+    master.mem_write_u8(0xFF01, 0x99);
+    master.mem_write_u8(0xFF02, 0x81);
+
+    for _ in 0..8 * BIT_CYCLES / 4 {
+        slave.execute_cycle(4);
+        master.execute_cycle(4);
+    }
+    assert_eq!(master.mem_read_u8(0xFF01), 0x42);
+    assert_eq!(master.mem_read_u8(0xFF02), 0x7F);
+    assert!(master.interrupt.contains(Interrupt::SERIAL));
+
+    slave.execute_cycle(4);
+    assert_eq!(slave.mem_read_u8(0xFF01), 0x99);
+    assert!(slave.interrupt.contains(Interrupt::SERIAL));
+}
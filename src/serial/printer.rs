@@ -0,0 +1,441 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use super::SerialDevice;
+use crate::ppu::rgb555_to_rgb888;
+
+const WIDTH: usize = 160;
+/// 2 rows of 20 tiles
+const DATA_PACKET_SIZE: usize = 640;
+/// the printer holds 9 data packets, 160x144 pixels
+const BUFFER_SIZE: usize = 9 * DATA_PACKET_SIZE;
+/// status requests answered busy after a print, which lets games show their animation
+const PRINT_POLLS: u8 = 4;
+/// gray levels of the 4 shades in PGM printouts
+const PAPER_SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 0b0000_0001;
+const STATUS_BUSY: u8 = 0b0000_0010;
+const STATUS_FULL: u8 = 0b0000_0100;
+const STATUS_UNPROCESSED: u8 = 0b0000_1000;
+const STATUS_PACKET_ERROR: u8 = 0b0001_0000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageFormat {
+    Png,
+    Pgm,
+}
+
+impl ImageFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Pgm => "pgm",
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(ImageFormat::Png),
+            "pgm" => Ok(ImageFormat::Pgm),
+            _ => Err(format!("unknown image format {s}, expected png or pgm")),
+        }
+    }
+}
+
+/// Position in the packet being received
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    /// the printer answers 0x81
+    Alive,
+    /// the printer answers its status
+    Status,
+}
+
+/// Game Boy Printer, writes every printout to an image in `output_dir`
+/// https://gbdev.io/pandocs/Gameboy_Printer.html
+///
+/// Packets: 0x88 0x33, command, compression, data length (LE), data,
+/// checksum of everything after the magic bytes (LE), then 2 bytes for the
+/// printer to answer 0x81 and its status.
+pub struct Printer {
+    phase: Phase,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    /// status requests left before the print is over
+    busy_polls: u8,
+    /// 2bpp tile data waiting for a print command
+    buffer: Vec<u8>,
+    /// shades of the printout being fed, `WIDTH` per line. Prints without
+    /// a margin after them are joined in a single image
+    page: Vec<u8>,
+    output_dir: PathBuf,
+    format: ImageFormat,
+    /// RGB555 color of each shade in PNG printouts
+    colors: [u16; 4],
+    /// printouts written so far, numbers the files
+    count: usize,
+}
+
+impl Printer {
+    /// PNG printouts are in `colors`, the DMG palette of the screen,
+    /// PGM ones in grayscale
+    pub fn new(output_dir: PathBuf, format: ImageFormat, colors: [u16; 4]) -> Self {
+        Printer {
+            phase: Phase::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            busy_polls: 0,
+            buffer: Vec::new(),
+            page: Vec::new(),
+            output_dir,
+            format,
+            colors,
+            count: 0,
+        }
+    }
+
+    /// Take one byte of a packet, return the byte sent back at the same time
+    fn receive(&mut self, byte: u8) -> u8 {
+        if matches!(
+            self.phase,
+            Phase::Command
+                | Phase::Compression
+                | Phase::LengthLow
+                | Phase::LengthHigh
+                | Phase::Data
+        ) {
+            self.checksum = self.checksum.wrapping_add(byte as u16);
+        }
+
+        let mut response = 0x00;
+        self.phase = match self.phase {
+            Phase::Magic1 if byte == 0x88 => Phase::Magic2,
+            Phase::Magic1 => Phase::Magic1,
+            Phase::Magic2 if byte == 0x33 => {
+                self.checksum = 0;
+                Phase::Command
+            }
+            Phase::Magic2 => Phase::Magic1,
+            Phase::Command => {
+                self.command = byte;
+                Phase::Compression
+            }
+            Phase::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                Phase::LengthLow
+            }
+            Phase::LengthLow => {
+                self.length = byte as usize;
+                Phase::LengthHigh
+            }
+            Phase::LengthHigh => {
+                self.length |= (byte as usize) << 8;
+                self.data.clear();
+                if self.length > 0 {
+                    Phase::Data
+                } else {
+                    Phase::ChecksumLow
+                }
+            }
+            Phase::Data => {
+                self.data.push(byte);
+                if self.data.len() == self.length {
+                    Phase::ChecksumLow
+                } else {
+                    Phase::Data
+                }
+            }
+            Phase::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                Phase::ChecksumHigh
+            }
+            Phase::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                Phase::Alive
+            }
+            Phase::Alive => {
+                response = 0x81;
+                if self.received_checksum == self.checksum {
+                    self.status &= !STATUS_CHECKSUM_ERROR;
+                    self.execute();
+                } else {
+                    self.status |= STATUS_CHECKSUM_ERROR;
+                }
+                Phase::Status
+            }
+            Phase::Status => {
+                response = self.status;
+                Phase::Magic1
+            }
+        };
+        response
+    }
+
+    fn execute(&mut self) {
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+            }
+            DATA => {
+                let data = std::mem::take(&mut self.data);
+                if self.compressed {
+                    decompress(&data, &mut self.buffer);
+                } else {
+                    self.buffer.extend_from_slice(&data);
+                }
+                self.buffer.truncate(BUFFER_SIZE);
+                self.status |= STATUS_UNPROCESSED;
+                if self.buffer.len() == BUFFER_SIZE {
+                    self.status |= STATUS_FULL;
+                }
+            }
+            PRINT if self.data.len() == 4 => {
+                let (margins, palette) = (self.data[1], self.data[2]);
+                self.print(palette);
+                // the lower nibble is the feed after the printout
+                if margins & 0x0F != 0 {
+                    self.feed();
+                }
+                self.status = (self.status & !(STATUS_UNPROCESSED | STATUS_FULL)) | STATUS_BUSY;
+                self.busy_polls = PRINT_POLLS;
+            }
+            PRINT => self.status |= STATUS_PACKET_ERROR,
+            STATUS => {
+                self.busy_polls = self.busy_polls.saturating_sub(1);
+                if self.busy_polls == 0 {
+                    self.status &= !STATUS_BUSY;
+                }
+            }
+            _ => self.status |= STATUS_PACKET_ERROR,
+        }
+    }
+
+    /// Move the buffered tiles to the page, through `palette` (like BGP)
+    fn print(&mut self, palette: u8) {
+        // 0x00 is the default palette
+        let palette = if palette == 0 { 0xE4 } else { palette };
+        let tile_rows = self.buffer.len() / (WIDTH / 8 * 16);
+
+        for tile_row in 0..tile_rows {
+            for y in 0..8 {
+                for x in 0..WIDTH {
+                    let tile = tile_row * WIDTH / 8 + x / 8;
+                    let lo = self.buffer[tile * 16 + y * 2];
+                    let hi = self.buffer[tile * 16 + y * 2 + 1];
+                    let bit = 7 - (x % 8);
+                    let color = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+                    self.page.push((palette >> (color * 2)) & 0b11);
+                }
+            }
+        }
+        self.buffer.clear();
+    }
+
+    /// The paper comes out: write the page to a new image
+    fn feed(&mut self) {
+        if self.page.is_empty() {
+            return;
+        }
+
+        self.count += 1;
+        let path = self.output_dir.join(format!(
+            "print_{:03}.{}",
+            self.count,
+            self.format.extension()
+        ));
+        let result = fs::create_dir_all(&self.output_dir).and_then(|_| match self.format {
+            ImageFormat::Png => write_png(&path, &self.page, self.colors),
+            ImageFormat::Pgm => write_pgm(&path, &self.page),
+        });
+        match result {
+            Ok(()) => eprintln!("Printed {}", path.display()),
+            Err(e) => eprintln!("Could not write the printout {}: {e}", path.display()),
+        }
+        self.page.clear();
+    }
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.receive(data)
+    }
+}
+
+impl Drop for Printer {
+    /// Keep a printout the game didn't feed
+    fn drop(&mut self) {
+        self.feed();
+    }
+}
+
+/// Run-length encoding of the data packets: a control byte with bit 7 set
+/// repeats the next byte `(control & 0x7F) + 2` times, otherwise the next
+/// `control + 1` bytes are copied
+fn decompress(data: &[u8], output: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            let Some(&byte) = data.get(i) else {
+                return;
+            };
+            output.extend(std::iter::repeat_n(byte, (control & 0x7F) as usize + 2));
+            i += 1;
+        } else {
+            let end = (i + control as usize + 1).min(data.len());
+            output.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+}
+
+fn write_png(path: &PathBuf, shades: &[u8], colors: [u16; 4]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        WIDTH as u32,
+        (shades.len() / WIDTH) as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let pixels: Vec<u8> = shades
+        .iter()
+        .flat_map(|&shade| {
+            let [_, r, g, b] = rgb555_to_rgb888(colors[shade as usize]).to_be_bytes();
+            [r, g, b]
+        })
+        .collect();
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(io::Error::other)
+}
+
+/// Binary PGM
+fn write_pgm(path: &PathBuf, shades: &[u8]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P5\n{} {}\n255\n", WIDTH, shades.len() / WIDTH)?;
+    let pixels: Vec<u8> = shades
+        .iter()
+        .map(|&shade| PAPER_SHADES[shade as usize])
+        .collect();
+    file.write_all(&pixels)?;
+    file.flush()
+}
+
+/// Send a whole packet, with a wrong checksum if `corrupt`, return the status
+#[cfg(test)]
+fn send_packet(
+    printer: &mut Printer,
+    command: u8,
+    compressed: bool,
+    data: &[u8],
+    corrupt: bool,
+) -> u8 {
+    let length = (data.len() as u16).to_le_bytes();
+    let mut body = vec![command, compressed as u8, length[0], length[1]];
+    body.extend_from_slice(data);
+    let mut checksum = body.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+    if corrupt {
+        checksum = !checksum;
+    }
+
+    let mut packet = vec![0x88, 0x33];
+    packet.extend(body);
+    packet.extend(checksum.to_le_bytes());
+    packet.extend([0x00, 0x00]);
+    let reply: Vec<u8> = packet.into_iter().map(|b| printer.exchange(b)).collect();
+    assert_eq!(reply[reply.len() - 2], 0x81);
+    reply[reply.len() - 1]
+}
+
+#[test]
+fn test_printer_packets() {
+    let mut printer = Printer::new(std::env::temp_dir(), ImageFormat::Pgm, [0; 4]);
+    assert_eq!(send_packet(&mut printer, INIT, false, &[], false), 0x00);
+
+    // 2 rows of black tiles, but the last line of the last tile is white
+    let mut data = [0xFF, 0xFF].repeat(4);
+    data.extend([0x80 | 120, 0xFF, 0x01, 0x00, 0x00]);
+    let status = send_packet(&mut printer, DATA, true, &data, false);
+    assert_eq!(status, STATUS_UNPROCESSED);
+
+    let status = send_packet(&mut printer, STATUS, false, &[0x00], true);
+    assert_ne!(status & STATUS_CHECKSUM_ERROR, 0);
+
+    // no margin after it, the page stays in the printer
+    let status = send_packet(&mut printer, PRINT, false, &[0x01, 0x10, 0xE4, 0x40], false);
+    assert_eq!(status, STATUS_BUSY);
+    assert_eq!(printer.page.len(), 16 * WIDTH);
+    assert_eq!(printer.page[0], 3);
+    assert_eq!(printer.page[16 * WIDTH - 1], 0);
+
+    let statuses: Vec<u8> = (0..PRINT_POLLS)
+        .map(|_| send_packet(&mut printer, STATUS, false, &[], false))
+        .collect();
+    assert_eq!(statuses, [STATUS_BUSY, STATUS_BUSY, STATUS_BUSY, 0x00]);
+    printer.page.clear();
+}
+
+#[test]
+fn test_printout_colors() {
+    use crate::ppu::DMG_COLORS;
+
+    let dir = std::env::temp_dir().join("gb_emulator_test_printout_colors");
+    let _ = fs::remove_dir_all(&dir);
+    let mut printer = Printer::new(dir.clone(), ImageFormat::Png, DMG_COLORS);
+    printer.page = [0, 1, 2, 3].repeat(WIDTH / 4);
+    printer.feed();
+
+    let decoder = png::Decoder::new(File::open(dir.join("print_001.png")).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    for (shade, color) in DMG_COLORS.iter().enumerate() {
+        let [_, r, g, b] = rgb555_to_rgb888(*color).to_be_bytes();
+        assert_eq!(pixels[shade * 3..shade * 3 + 3], [r, g, b]);
+    }
+
+    printer.format = ImageFormat::Pgm;
+    printer.page = [0, 1, 2, 3].repeat(WIDTH / 4);
+    printer.feed();
+    let pgm = fs::read(dir.join("print_002.pgm")).unwrap();
+    assert_eq!(pgm[pgm.len() - 4..], PAPER_SHADES);
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,221 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use super::SerialDevice;
+
+/// Messages are 2 bytes: a tag and a value
+const HELLO: u8 = 0x01;
+/// waiting for the other side's clock, the value is SB
+const READY: u8 = 0x02;
+/// transfer clocked by the sender, the value is its SB
+const TRANSFER: u8 = 0x03;
+/// end of a lockstep period
+const SYNC: u8 = 0x04;
+
+/// T-cycles between two synchronisations in lockstep, the duration of one
+/// byte with the normal clock
+const SYNC_CYCLES: u32 = 4096;
+/// T-cycles between two reads of the socket when running freely
+const POLL_CYCLES: u32 = 512;
+
+/// Link cable to another `gb_emulator` process over TCP.
+///
+/// The side whose game selects the internal clock is the master of the transfer.
+/// The other side announces its SB when it starts waiting for an external clock,
+/// so the master gets the byte to shift in without waiting for a round trip,
+/// and sends its own byte back. A master with nobody waiting reads 0xFF.
+///
+/// Both processes run freely by default. In lockstep, they stop every
+/// `SYNC_CYCLES` until the other one got there, and only look at the messages
+/// at these points: the exchanges then happen at the same emulated cycles
+/// on every run, at the cost of speed.
+pub struct TcpLink {
+    /// `None` once the other side is gone
+    stream: Option<TcpStream>,
+    lockstep: bool,
+    /// bytes of a message split between two reads
+    buffer: Vec<u8>,
+    /// T-cycles since the last synchronisation or read of the socket
+    elapsed: u32,
+    /// SB of the other side while it waits for our clock
+    peer_waiting: Option<u8>,
+    /// byte clocked in by the other side
+    received: Option<u8>,
+    /// SB we told the other side we are waiting with
+    announced: Option<u8>,
+}
+
+impl TcpLink {
+    /// Wait for the other emulator to connect
+    pub fn listen(addr: impl ToSocketAddrs, lockstep: bool) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Self::new(stream, lockstep)
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs, lockstep: bool) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?, lockstep)
+    }
+
+    /// Check that both sides agree on the lockstep mode
+    pub fn new(mut stream: TcpStream, lockstep: bool) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.write_all(&[HELLO, lockstep as u8])?;
+
+        let mut hello = [0; 2];
+        stream.read_exact(&mut hello)?;
+        match hello {
+            [HELLO, peer] if peer == lockstep as u8 => (),
+            [HELLO, _] => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "only one side of the link uses lockstep",
+                ))
+            }
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "the other side is not a gb_emulator link",
+                ))
+            }
+        }
+        stream.set_nonblocking(!lockstep)?;
+
+        Ok(TcpLink {
+            stream: Some(stream),
+            lockstep,
+            buffer: Vec::new(),
+            elapsed: 0,
+            peer_waiting: None,
+            received: None,
+            announced: None,
+        })
+    }
+
+    fn disconnect(&mut self, e: io::Error) {
+        eprintln!("Link cable disconnected: {e}");
+        self.stream = None;
+        self.peer_waiting = None;
+    }
+
+    fn send(&mut self, tag: u8, value: u8) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+        let mut message = &[tag, value][..];
+        // the socket doesn't block when running freely, but 2 bytes hardly ever fill it
+        while !message.is_empty() {
+            match stream.write(message) {
+                Ok(0) => return self.disconnect(ErrorKind::WriteZero.into()),
+                Ok(n) => message = &message[n..],
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return self.disconnect(e),
+            }
+        }
+    }
+
+    /// Handle the messages received so far. In lockstep, block until the next
+    /// SYNC instead
+    fn receive(&mut self) {
+        loop {
+            while self.buffer.len() >= 2 {
+                let (tag, value) = (self.buffer[0], self.buffer[1]);
+                self.buffer.drain(..2);
+                match tag {
+                    READY => self.peer_waiting = Some(value),
+                    TRANSFER => self.received = Some(value),
+                    SYNC if self.lockstep => return,
+                    SYNC => (),
+                    _ => {
+                        let e = io::Error::new(ErrorKind::InvalidData, "unknown message");
+                        return self.disconnect(e);
+                    }
+                }
+            }
+
+            let Some(stream) = &mut self.stream else {
+                return;
+            };
+            let mut data = [0; 64];
+            match stream.read(&mut data) {
+                Ok(0) => return self.disconnect(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.buffer.extend_from_slice(&data[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return self.disconnect(e),
+            }
+        }
+    }
+}
+
+impl SerialDevice for TcpLink {
+    fn exchange(&mut self, data: u8) -> u8 {
+        match self.peer_waiting.take() {
+            Some(received) => {
+                self.send(TRANSFER, data);
+                received
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn poll(&mut self, data: u8) -> Option<u8> {
+        if let Some(received) = self.received.take() {
+            self.announced = None;
+            return Some(received);
+        }
+        if self.announced != Some(data) {
+            self.announced = Some(data);
+            self.send(READY, data);
+        }
+        None
+    }
+
+    fn execute_cycle(&mut self, time: u32) {
+        self.elapsed += time;
+        if self.lockstep {
+            while self.elapsed >= SYNC_CYCLES && self.stream.is_some() {
+                self.elapsed -= SYNC_CYCLES;
+                self.send(SYNC, 0);
+                self.receive();
+            }
+        } else if self.elapsed >= POLL_CYCLES {
+            self.elapsed = 0;
+            self.receive();
+        }
+    }
+}
+
+#[test]
+fn test_lockstep_transfer() {
+    use super::Serial;
+    use crate::cpu::Mem;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // write SB and SC at cycle `start`, return them after 4 synchronisations
+    let run = |link: TcpLink, start: u32, data: u8, control: u8| {
+        let mut serial = Serial::new(Box::new(link), false);
+        for cycle in (0..4 * SYNC_CYCLES).step_by(4) {
+            if cycle == start {
+                serial.mem_write_u8(0xFF01, data);
+                serial.mem_write_u8(0xFF02, control);
+            }
+            serial.execute_cycle(4);
+        }
+        (serial.mem_read_u8(0xFF01), serial.mem_read_u8(0xFF02))
+    };
+
+    let slave = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        run(TcpLink::new(stream, true).unwrap(), 0, 0x42, 0x80)
+    });
+    // the master sees the slave waiting after the first synchronisation
+    let master = run(TcpLink::connect(addr, true).unwrap(), 4100, 0x99, 0x81);
+    let slave = slave.join().unwrap();
+    assert_eq!(master, (0x42, 0x7F));
+    assert_eq!(slave, (0x99, 0x7E));
+}
@@ -0,0 +1,286 @@
+//! Per-instruction CPU tests against the SingleStepTests sm83 JSON vectors
+//! https://github.com/SingleStepTests/sm83
+//!
+//! The vectors aren't part of the repository: put the `v1/*.json` files in
+//! `sm83_tests/` (or point `GB_SM83_TESTS` to them). Each file holds the tests
+//! of one opcode: initial registers and RAM, then the expected registers, RAM
+//! and bus activity after executing one instruction.
+
+use std::{
+    cell::Cell,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use crate::{
+    cpu::{Bus, Mem, StatusFlags, CPU},
+    error::EmulatorError,
+    interrupt::InterruptController,
+    test_rom::read_known_failures,
+};
+
+const DEFAULT_TEST_DIR: &str = "sm83_tests";
+
+/// 64 KiB of RAM and nothing else, which records the writes of the CPU
+struct FlatMemory {
+    ram: Vec<u8>,
+    writes: Vec<(u16, u8)>,
+    /// T-cycles elapsed
+    cycles: u32,
+    interrupt: InterruptController,
+    fault: Cell<Option<EmulatorError>>,
+}
+
+impl FlatMemory {
+    fn new() -> Self {
+        FlatMemory {
+            ram: vec![0; 0x10000],
+            writes: Vec::new(),
+            cycles: 0,
+            interrupt: InterruptController::new(),
+            fault: Cell::new(None),
+        }
+    }
+}
+
+impl Mem for FlatMemory {
+    fn mem_read_u8(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn mem_write_u8(&mut self, addr: u16, data: u8) {
+        self.ram[addr as usize] = data;
+        self.writes.push((addr, data));
+    }
+}
+
+impl Bus for FlatMemory {
+    fn execute_cycle(&mut self, time: u32) {
+        self.cycles += time;
+    }
+
+    fn interrupts(&mut self) -> &mut InterruptController {
+        &mut self.interrupt
+    }
+
+    fn joypad_pressed(&self) -> bool {
+        false
+    }
+
+    fn take_dma_stall(&mut self) -> u32 {
+        0
+    }
+
+    fn try_switch_speed(&mut self) -> bool {
+        false
+    }
+
+    fn reset_divider(&mut self) {}
+
+    fn raise(&self, error: EmulatorError) {
+        self.fault.set(Some(error));
+    }
+}
+
+fn field(state: &Value, name: &str) -> u16 {
+    state[name].as_u64().unwrap_or(0) as u16
+}
+
+fn set_state(cpu: &mut CPU<FlatMemory>, state: &Value) {
+    cpu.program_counter = field(state, "pc");
+    cpu.stack_pointer = field(state, "sp");
+    cpu.a = field(state, "a") as u8;
+    cpu.status = StatusFlags::from_bits_truncate(field(state, "f") as u8);
+    cpu.b = field(state, "b") as u8;
+    cpu.c = field(state, "c") as u8;
+    cpu.d = field(state, "d") as u8;
+    cpu.e = field(state, "e") as u8;
+    cpu.h = field(state, "h") as u8;
+    cpu.l = field(state, "l") as u8;
+    cpu.ime = field(state, "ime") != 0;
+    cpu.mmu.interrupt.enable = field(state, "ie") as u8;
+    for entry in state["ram"].as_array().into_iter().flatten() {
+        cpu.mmu.ram[entry[0].as_u64().unwrap() as usize] = entry[1].as_u64().unwrap() as u8;
+    }
+}
+
+/// Differences between the CPU and the expected final state
+fn compare_state(cpu: &CPU<FlatMemory>, state: &Value) -> Vec<String> {
+    let registers = [
+        ("pc", cpu.program_counter),
+        ("sp", cpu.stack_pointer),
+        ("a", cpu.a as u16),
+        ("f", cpu.status.bits() as u16),
+        ("b", cpu.b as u16),
+        ("c", cpu.c as u16),
+        ("d", cpu.d as u16),
+        ("e", cpu.e as u16),
+        ("h", cpu.h as u16),
+        ("l", cpu.l as u16),
+        ("ime", cpu.ime as u16),
+    ];
+
+    let mut errors = Vec::new();
+    for (name, value) in registers {
+        let expected = field(state, name);
+        if value != expected {
+            errors.push(format!("{name} is {value:#x} instead of {expected:#x}"));
+        }
+    }
+    for entry in state["ram"].as_array().into_iter().flatten() {
+        let addr = entry[0].as_u64().unwrap() as u16;
+        let expected = entry[1].as_u64().unwrap() as u8;
+        let value = cpu.mmu.ram[addr as usize];
+        if value != expected {
+            errors.push(format!(
+                "({addr:#06x}) is {value:#x} instead of {expected:#x}"
+            ));
+        }
+    }
+    errors
+}
+
+/// Run one test vector, return what went wrong.
+///
+/// The CPU executes a whole instruction at once rather than one M-cycle at a
+/// time, so the bus activity is only checked for the number of M-cycles
+/// and the writes, in order
+fn run_test(test: &Value) -> Result<(), String> {
+    let mut cpu = CPU::with_bus(FlatMemory::new());
+    set_state(&mut cpu, &test["initial"]);
+
+    let time = cpu.step();
+    if let Some(error) = cpu.mmu.fault.take() {
+        return Err(error.to_string());
+    }
+
+    let mut errors = compare_state(&cpu, &test["final"]);
+
+    let cycles = test["cycles"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+    if time as usize != cycles.len() * 4 {
+        errors.push(format!(
+            "took {} M-cycles instead of {}",
+            time / 4,
+            cycles.len()
+        ));
+    }
+    let writes: Vec<(u16, u8)> = cycles
+        .iter()
+        .filter(|cycle| cycle[2].as_str().is_some_and(|kind| kind.contains('w')))
+        .map(|cycle| {
+            (
+                cycle[0].as_u64().unwrap() as u16,
+                cycle[1].as_u64().unwrap() as u8,
+            )
+        })
+        .collect();
+    if cpu.mmu.writes != writes {
+        errors.push(format!(
+            "wrote {:x?} instead of {writes:x?}",
+            cpu.mmu.writes
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join(", "))
+    }
+}
+
+/// Run every test of a vector file, return how many passed and the first failure
+fn run_file(path: &Path) -> (usize, usize, Option<String>) {
+    let tests: Value = fs::read_to_string(path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_else(|| panic!("{} is not a test vector file", path.display()));
+    let tests = tests.as_array().map(Vec::as_slice).unwrap_or(&[]);
+
+    let mut passed = 0;
+    let mut first_failure = None;
+    for test in tests {
+        match run_test(test) {
+            Ok(()) => passed += 1,
+            Err(e) => {
+                first_failure.get_or_insert_with(|| format!("{}: {e}", test["name"]));
+            }
+        }
+    }
+    (passed, tests.len(), first_failure)
+}
+
+#[test]
+fn test_harness() {
+    // LD B,C / RLC B / LD (HL),A
+    let tests: Value = serde_json::from_str(
+        r#"[
+        {"name": "41 0000",
+         "initial": {"pc": 256, "sp": 65534, "a": 1, "b": 2, "c": 3, "d": 4, "e": 5, "f": 176,
+                     "h": 6, "l": 7, "ime": 0, "ram": [[256, 65]]},
+         "final": {"pc": 257, "sp": 65534, "a": 1, "b": 3, "c": 3, "d": 4, "e": 5, "f": 176,
+                   "h": 6, "l": 7, "ime": 0, "ram": [[256, 65]]},
+         "cycles": [[256, 65, "r-m"]]},
+        {"name": "cb 00 0000",
+         "initial": {"pc": 256, "sp": 65534, "a": 0, "b": 133, "c": 0, "d": 0, "e": 0, "f": 0,
+                     "h": 0, "l": 0, "ime": 0, "ram": [[256, 203], [257, 0]]},
+         "final": {"pc": 258, "sp": 65534, "a": 0, "b": 11, "c": 0, "d": 0, "e": 0, "f": 16,
+                   "h": 0, "l": 0, "ime": 0, "ram": [[256, 203], [257, 0]]},
+         "cycles": [[256, 203, "r-m"], [257, 0, "r-m"]]},
+        {"name": "77 0000",
+         "initial": {"pc": 256, "sp": 65534, "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0,
+                     "h": 192, "l": 0, "ime": 0, "ram": [[256, 119]]},
+         "final": {"pc": 257, "sp": 65534, "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0,
+                   "h": 192, "l": 0, "ime": 0, "ram": [[256, 119], [49152, 66]]},
+         "cycles": [[256, 119, "r-m"], [49152, 66, "-wm"]]}
+    ]"#,
+    )
+    .unwrap();
+
+    for test in tests.as_array().unwrap() {
+        assert_eq!(run_test(test), Ok(()), "{}", test["name"]);
+    }
+}
+
+/// Report the pass rate of every opcode. Opcodes listed in the
+/// `known_failures.txt` of the directory (by file name) may fail
+#[test]
+fn test_sm83() {
+    let dir = std::env::var_os("GB_SM83_TESTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(DEFAULT_TEST_DIR));
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .collect();
+    if files.is_empty() {
+        eprintln!("No sm83 test vectors in {}, skipping", dir.display());
+        return;
+    }
+    files.sort();
+
+    let known_failures = read_known_failures(&dir);
+    let mut regressions = Vec::new();
+    for path in files {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let (passed, total, first_failure) = run_file(&path);
+        let known = known_failures.contains(&name);
+        match first_failure {
+            None if known => {
+                eprintln!("{name}: {passed}/{total}, remove it from the known failures")
+            }
+            None => eprintln!("{name}: {passed}/{total}"),
+            Some(failure) => {
+                eprintln!("{name}: {passed}/{total}, {failure}");
+                if !known {
+                    regressions.push(name);
+                }
+            }
+        }
+    }
+    assert!(regressions.is_empty(), "failed: {}", regressions.join(", "));
+}
@@ -0,0 +1,208 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    apu::CPU_FREQUENCY,
+    cartridge::clock::CycleClock,
+    config::Config,
+    cpu::{Mem, CPU},
+    error::EmulatorError,
+    serial::Capture,
+};
+
+/// Directory of the ROMs run by `cargo test`, unless `GB_TEST_ROMS` is set
+#[cfg(test)]
+const DEFAULT_ROM_DIR: &str = "test_roms";
+/// ROMs of the directory expected to fail, one path (relative to it) per line
+#[cfg(test)]
+const KNOWN_FAILURES: &str = "known_failures.txt";
+/// Emulated seconds before giving up on a ROM
+pub const DEFAULT_TIMEOUT: u64 = 60;
+
+/// LD B,B, the breakpoint mooneye's test ROMs hit when they're done
+const LD_B_B: u8 = 0x40;
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+#[derive(PartialEq, Debug)]
+pub enum TestOutcome {
+    Passed,
+    /// with the serial output, if any
+    Failed(String),
+    TimedOut(String),
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (result, output) = match self {
+            TestOutcome::Passed => return write!(f, "passed"),
+            TestOutcome::Failed(output) => ("failed", output),
+            TestOutcome::TimedOut(output) => ("timed out", output),
+        };
+        write!(f, "{result}")?;
+        if !output.trim().is_empty() {
+            write!(f, "\n{}", output.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Run a test ROM headless until it reports a result, or `timeout` seconds of
+/// emulated time went by. Understands Blargg's ROMs, which print "Passed" or
+/// "Failed" on the serial port, and mooneye's, which leave the Fibonacci
+/// sequence in B, C, D, E, H and L before executing LD B,B
+pub fn run_test_rom(path: PathBuf, timeout: u64) -> Result<TestOutcome, EmulatorError> {
+    let capture = Capture::default();
+    let config = Config {
+        clock: Box::new(CycleClock::new(0)),
+        serial: Box::new(capture.clone()),
+        ..Config::default()
+    };
+    let mut cpu = CPU::with_config(path, config)?;
+    // they don't save anything worth keeping
+    cpu.mmu.set_autosave_interval(0);
+
+    let timeout = timeout * CPU_FREQUENCY as u64;
+    let mut next_check = 0;
+    while cpu.mmu.cycles < timeout {
+        if cpu.mem_read_u8(cpu.program_counter) == LD_B_B && !cpu.halted {
+            let registers = [
+                cpu.get_b(),
+                cpu.get_c(),
+                cpu.get_d(),
+                cpu.get_e(),
+                cpu.get_h(),
+                cpu.get_l(),
+            ];
+            if registers == MOONEYE_PASS {
+                return Ok(TestOutcome::Passed);
+            }
+            if registers == MOONEYE_FAIL {
+                return Ok(TestOutcome::Failed(serial_text(&capture)));
+            }
+        }
+
+        cpu.step();
+        if let Some(error) = cpu.mmu.take_fault() {
+            return Err(error);
+        }
+
+        // the serial output only changes every few thousand cycles
+        if cpu.mmu.cycles >= next_check {
+            next_check = cpu.mmu.cycles + 0x10000;
+            let output = serial_text(&capture);
+            if output.contains("Passed") {
+                return Ok(TestOutcome::Passed);
+            }
+            if output.contains("Failed") {
+                return Ok(TestOutcome::Failed(output));
+            }
+        }
+    }
+
+    Ok(TestOutcome::TimedOut(serial_text(&capture)))
+}
+
+fn serial_text(capture: &Capture) -> String {
+    String::from_utf8_lossy(&capture.output()).into_owned()
+}
+
+/// Every .gb and .gbc file under `dir`, sorted
+pub fn find_test_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return roms;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            roms.extend(find_test_roms(&path));
+        } else if matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("gb" | "gbc")
+        ) {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    roms
+}
+
+/// Entries of the `known_failures.txt` of a test directory, ignoring blank
+/// lines and comments
+#[cfg(test)]
+pub fn read_known_failures(dir: &Path) -> Vec<String> {
+    fs::read_to_string(dir.join(KNOWN_FAILURES))
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn test_mooneye_signature() {
+    let path = std::env::temp_dir().join("gb_emulator_test_mooneye_signature.gb");
+    let mut rom = vec![0; 0x8000];
+    // LD B,3 / LD C,5 / LD D,8 / LD E,13 / LD H,21 / LD L,34 / LD B,B
+    let program = [
+        0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, LD_B_B,
+    ];
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(&program);
+    std::fs::write(&path, &rom).unwrap();
+    assert_eq!(run_test_rom(path.clone(), 1).unwrap(), TestOutcome::Passed);
+
+    // an endless loop (JR -2)
+    rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]);
+    std::fs::write(&path, &rom).unwrap();
+    let outcome = run_test_rom(path.clone(), 1).unwrap();
+    assert_eq!(outcome, TestOutcome::TimedOut(String::new()));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Run every ROM of `test_roms/` (or `GB_TEST_ROMS`). The ROMs aren't part of
+/// the repository: the test passes when there are none. ROMs listed in
+/// `known_failures.txt` may fail, so only regressions make the test fail
+#[test]
+fn test_roms() {
+    let dir = std::env::var_os("GB_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(DEFAULT_ROM_DIR));
+    let roms = find_test_roms(&dir);
+    if roms.is_empty() {
+        eprintln!("No test ROMs in {}, skipping", dir.display());
+        return;
+    }
+
+    let known_failures = read_known_failures(&dir);
+
+    let mut regressions = Vec::new();
+    for rom in roms {
+        let name = rom
+            .strip_prefix(&dir)
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        let outcome = match run_test_rom(rom, DEFAULT_TIMEOUT) {
+            Ok(outcome) => outcome,
+            Err(e) => TestOutcome::Failed(e.to_string()),
+        };
+        let known = known_failures.contains(&name);
+        match (&outcome, known) {
+            (TestOutcome::Passed, true) => {
+                eprintln!("{name}: passed, remove it from {KNOWN_FAILURES}")
+            }
+            (TestOutcome::Passed, false) => eprintln!("{name}: passed"),
+            (_, true) => eprintln!("{name}: {outcome} (known failure)"),
+            (_, false) => {
+                eprintln!("{name}: {outcome}");
+                regressions.push(name);
+            }
+        }
+    }
+    assert!(regressions.is_empty(), "failed: {}", regressions.join(", "));
+}
@@ -1,60 +1,81 @@
-use crate::cpu::Mem;
+use crate::{cpu::Mem, interrupt::Interrupt};
 
+/// https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
+///
+/// DIV is the upper byte of a 16-bit system counter incremented every T-cycle.
+/// TIMA increments on the falling edge of the counter bit selected by TAC
+/// (ANDed with the TAC enable bit), which makes writes to DIV and TAC glitchy.
 pub struct Timer {
-    divider: u8,
+    system_counter: u16,
     counter: u8,
     modulo: u8,
     timer_ctrl: u8,
-    timer_enabled: bool,
-    clock_freq: u32,
-    div_internal: u32,
-    timer_internal: u32,
-    pub interrupt: u8,
+    /// TIMA overflowed during the last M-cycle and reads 0x00, it gets
+    /// reloaded from TMA (and the interrupt requested) during the next one
+    overflow: bool,
+    /// TMA is being copied to TIMA during the current M-cycle
+    reloading: bool,
+    pub interrupt: Interrupt,
 }
 
 impl Timer {
     pub fn new() -> Self {
         Timer {
-            divider: 0,
+            system_counter: 0,
             counter: 0,
             modulo: 0,
             timer_ctrl: 0,
-            timer_enabled: false,
-            clock_freq: 0,
-            div_internal: 0,
-            timer_internal: 0,
-            interrupt: 0,
+            overflow: false,
+            reloading: false,
+            interrupt: Interrupt::empty(),
         }
     }
 
+    /// DIV is the upper byte of `counter`
+    pub fn set_system_counter(&mut self, counter: u16) {
+        self.system_counter = counter;
+    }
+
     pub fn execute_cycle(&mut self, time: u32) {
-        self.div_internal += time;
-        while self.div_internal >= 256 {
-            self.divider = self.divider.wrapping_add(1);
-            self.div_internal -= 256;
+        for _ in 0..time / 4 {
+            self.execute_m_cycle();
         }
+    }
 
-        if !self.timer_enabled {
-            return;
+    fn execute_m_cycle(&mut self) {
+        self.reloading = false;
+        if self.overflow {
+            self.overflow = false;
+            self.reloading = true;
+            self.counter = self.modulo;
+            self.interrupt |= Interrupt::TIMER;
         }
 
-        while self.timer_internal >= self.clock_freq {
-            self.counter = self.counter.wrapping_add(1);
-            if self.counter == 0 {
-                self.counter = self.modulo;
-                self.interrupt |= 0b0000_0100;
-            }
-            self.timer_internal -= self.clock_freq;
+        let old = self.timer_bit();
+        self.system_counter = self.system_counter.wrapping_add(4);
+        self.detect_falling_edge(old);
+    }
+
+    /// Bit of the system counter selected by TAC, ANDed with the enable bit
+    fn timer_bit(&self) -> bool {
+        if self.timer_ctrl & 0b0000_0100 == 0 {
+            return false;
         }
+
+        let bit = match self.timer_ctrl & 0b0000_0011 {
+            0b01 => 3, // 262144 Hz
+            0b10 => 5, // 65536 Hz
+            0b11 => 7, // 16384 Hz
+            _ => 9,    // 4096 Hz
+        };
+        (self.system_counter >> bit) & 1 == 1
     }
 
-    fn extract_timer_ctrl_reg(&mut self) {
-        self.timer_enabled = (self.timer_ctrl & 0b0000_0100) == 0;
-        self.clock_freq = match self.timer_ctrl & 0b0000_0011 {
-            0b01 => 16,
-            0b10 => 64,
-            0b11 => 256,
-            _ => 1024,
+    fn detect_falling_edge(&mut self, old: bool) {
+        if old && !self.timer_bit() {
+            let (res, overflow) = self.counter.overflowing_add(1);
+            self.counter = res;
+            self.overflow |= overflow;
         }
     }
 }
@@ -62,24 +83,150 @@ impl Timer {
 impl Mem for Timer {
     fn mem_read_u8(&self, addr: u16) -> u8 {
         match addr {
-            0xFF04 => self.divider,
+            0xFF04 => (self.system_counter >> 8) as u8,
             0xFF05 => self.counter,
             0xFF06 => self.modulo,
-            0xFF07 => self.timer_ctrl,
+            0xFF07 => 0b1111_1000 | self.timer_ctrl,
             _ => panic!("Timer can't read {addr:4x}"),
         }
     }
 
     fn mem_write_u8(&mut self, addr: u16, data: u8) {
         match addr {
-            0xFF04 => self.divider = 0,
-            0xFF05 => self.counter = data,
-            0xFF06 => self.modulo = data,
+            0xFF04 => {
+                let old = self.timer_bit();
+                self.system_counter = 0;
+                self.detect_falling_edge(old);
+            }
+            0xFF05 => {
+                // written during the reload cycle: TMA wins
+                if !self.reloading {
+                    self.counter = data;
+                    // written during the overflow cycle: reload is aborted
+                    self.overflow = false;
+                }
+            }
+            0xFF06 => {
+                self.modulo = data;
+                if self.reloading {
+                    self.counter = data;
+                }
+            }
             0xFF07 => {
-                self.timer_ctrl = data;
-                self.extract_timer_ctrl_reg();
+                let old = self.timer_bit();
+                self.timer_ctrl = data & 0b0000_0111;
+                self.detect_falling_edge(old);
             }
-            _ => panic!("Timer can't read {addr:4x}"),
+            _ => panic!("Timer can't write {addr:4x}"),
         }
     }
 }
+
+#[test]
+fn test_tima_increment() {
+    let mut timer = Timer::new();
+    timer.mem_write_u8(0xFF07, 0b101); // 16 T-cycles per increment
+
+    timer.execute_cycle(64);
+    assert_eq!(timer.mem_read_u8(0xFF05), 4);
+
+    timer.execute_cycle(256 - 64);
+    assert_eq!(timer.mem_read_u8(0xFF04), 1);
+}
+
+#[test]
+fn test_tima_overflow_reload_delay() {
+    let mut timer = Timer::new();
+    timer.mem_write_u8(0xFF06, 0x42);
+    timer.mem_write_u8(0xFF05, 0xFF);
+    timer.mem_write_u8(0xFF07, 0b101);
+
+    timer.execute_cycle(16);
+    assert_eq!(timer.mem_read_u8(0xFF05), 0x00);
+    assert!(timer.interrupt.is_empty());
+
+    timer.execute_cycle(4);
+    assert_eq!(timer.mem_read_u8(0xFF05), 0x42);
+    assert!(timer.interrupt.contains(Interrupt::TIMER));
+}
+
+#[test]
+fn test_tima_write_aborts_reload() {
+    let mut timer = Timer::new();
+    timer.mem_write_u8(0xFF06, 0x42);
+    timer.mem_write_u8(0xFF05, 0xFF);
+    timer.mem_write_u8(0xFF07, 0b101);
+
+    timer.execute_cycle(16);
+    timer.mem_write_u8(0xFF05, 0x10);
+    timer.execute_cycle(4);
+    assert_eq!(timer.mem_read_u8(0xFF05), 0x10);
+    assert!(timer.interrupt.is_empty());
+}
+
+#[test]
+fn test_div_write_glitch() {
+    let mut timer = Timer::new();
+    timer.mem_write_u8(0xFF07, 0b101);
+
+    // counter bit 3 is set after 8 T-cycles, resetting DIV makes it fall
+    timer.execute_cycle(8);
+    assert_eq!(timer.mem_read_u8(0xFF05), 0);
+    timer.mem_write_u8(0xFF04, 0x12);
+    assert_eq!(timer.mem_read_u8(0xFF05), 1);
+    assert_eq!(timer.mem_read_u8(0xFF04), 0);
+}
+
+#[cfg(test)]
+fn overflowing_timer() -> Timer {
+    let mut timer = Timer::new();
+    timer.mem_write_u8(0xFF06, 0x42);
+    timer.mem_write_u8(0xFF05, 0xFF);
+    timer.mem_write_u8(0xFF07, 0b101);
+    // overflow cycle, then reload cycle
+    timer.execute_cycle(16);
+    timer.execute_cycle(4);
+    assert!(timer.reloading);
+    timer
+}
+
+#[test]
+fn test_tima_write_on_reload_cycle() {
+    let mut timer = overflowing_timer();
+    timer.mem_write_u8(0xFF05, 0x10);
+    assert_eq!(timer.mem_read_u8(0xFF05), 0x42);
+    assert!(timer.interrupt.contains(Interrupt::TIMER));
+
+    // the next M-cycle takes writes again
+    timer.execute_cycle(4);
+    timer.mem_write_u8(0xFF05, 0x10);
+    assert_eq!(timer.mem_read_u8(0xFF05), 0x10);
+}
+
+#[test]
+fn test_tma_write_on_reload_cycle() {
+    let mut timer = overflowing_timer();
+    timer.mem_write_u8(0xFF06, 0x77);
+    assert_eq!(timer.mem_read_u8(0xFF05), 0x77);
+
+    // only TMA changes afterwards
+    timer.execute_cycle(4);
+    timer.mem_write_u8(0xFF06, 0x99);
+    assert_eq!(timer.mem_read_u8(0xFF05), 0x77);
+    assert_eq!(timer.mem_read_u8(0xFF06), 0x99);
+}
+
+#[test]
+fn test_tac_write_glitch() {
+    let mut timer = Timer::new();
+    timer.mem_write_u8(0xFF07, 0b101);
+    timer.execute_cycle(8);
+
+    // bit 3 is set, bit 9 (4096 Hz) isn't: switching falls, TIMA increments
+    timer.mem_write_u8(0xFF07, 0b100);
+    assert_eq!(timer.mem_read_u8(0xFF05), 1);
+    // so does disabling the timer while the bit is set
+    timer.mem_write_u8(0xFF07, 0b101);
+    timer.mem_write_u8(0xFF07, 0b001);
+    assert_eq!(timer.mem_read_u8(0xFF05), 2);
+}